@@ -1,5 +1,5 @@
 use ark_std::rand::Rng;
-use async_std::{io::BufReader, net::TcpStream, prelude::*, task};
+use async_std::{io::BufReader, prelude::*, task};
 use bench_utils::*;
 use clap::{App, Arg, ArgMatches};
 
@@ -57,11 +57,13 @@ fn main() {
 
     task::block_on(async move {
         // Form connections
-        let mut readers = Vec::with_capacity(16);
-        for _ in 0..16 {
-            let stream = TcpStream::connect(&server_addr).await.unwrap();
-            readers.push(CountingIO::new(BufReader::new(stream)));
-        }
+        let mut readers = IMuxAsync::connect(server_addr.as_str(), 16)
+            .await
+            .unwrap()
+            .into_inner()
+            .into_iter()
+            .map(|stream| CountingIO::new(BufReader::new(stream)))
+            .collect::<Vec<_>>();
 
         let read_time = start_timer!(|| "Reading buffer from 1 connection");
         // Read the message length
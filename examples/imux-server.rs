@@ -49,12 +49,13 @@ fn main() {
     task::block_on(async move {
         // Form connections
         let listener = TcpListener::bind(server_addr).await.unwrap();
-        let mut incoming = listener.incoming();
-        let mut writers = Vec::with_capacity(16);
-        for _ in 0..16 {
-            let stream = incoming.next().await.unwrap().unwrap();
-            writers.push(CountingIO::new(BufWriter::new(stream)));
-        }
+        let mut writers = IMuxAsync::accept(&listener, 16)
+            .await
+            .unwrap()
+            .into_inner()
+            .into_iter()
+            .map(|stream| CountingIO::new(BufWriter::new(stream)))
+            .collect::<Vec<_>>();
 
         let write_time = start_timer!(|| "Sending buffer across 1 connection");
         // Send the message length
@@ -1,5 +1,5 @@
 use ark_std::rand::Rng;
-use async_std::{io::BufReader, net::TcpStream, task};
+use async_std::{io::BufReader, task};
 use bench_utils::*;
 use clap::{App, Arg, ArgMatches};
 
@@ -57,11 +57,13 @@ fn main() {
 
     task::block_on(async move {
         // Form connections
-        let mut readers = Vec::with_capacity(16);
-        for _ in 0..16 {
-            let stream = TcpStream::connect(&server_addr).await.unwrap();
-            readers.push(BufReader::new(stream));
-        }
+        let readers = IMuxAsync::connect(server_addr.as_str(), 16)
+            .await
+            .unwrap()
+            .into_inner()
+            .into_iter()
+            .map(BufReader::new)
+            .collect::<Vec<_>>();
         let reader = ThreadedReader::new(IMuxAsync::new(readers));
 
         let recv_time = start_timer!(|| "Spawning threads");
@@ -72,7 +74,7 @@ fn main() {
                 s.spawn(move |_| {
                     task::block_on(async {
                         let recv_time = start_timer!(|| format!("Thread {} Receiving", i));
-                        let buf = reader.read().await;
+                        let buf = reader.read().await.unwrap();
                         end_timer!(recv_time);
                         assert_eq!(test_buf, buf.as_slice());
                     });
@@ -1,5 +1,5 @@
 use ark_std::rand::Rng;
-use async_std::{io::BufWriter, net::TcpListener, prelude::*, task};
+use async_std::{io::BufWriter, net::TcpListener, task};
 use bench_utils::*;
 use clap::{App, Arg, ArgMatches};
 
@@ -49,12 +49,13 @@ fn main() {
     task::block_on(async move {
         // Form connections
         let listener = TcpListener::bind(server_addr).await.unwrap();
-        let mut incoming = listener.incoming();
-        let mut writers = Vec::with_capacity(16);
-        for _ in 0..16 {
-            let stream = incoming.next().await.unwrap().unwrap();
-            writers.push(BufWriter::new(stream));
-        }
+        let writers = IMuxAsync::accept(&listener, 16)
+            .await
+            .unwrap()
+            .into_inner()
+            .into_iter()
+            .map(BufWriter::new)
+            .collect::<Vec<_>>();
         let writer = ThreadedWriter::new(IMuxAsync::new(writers));
 
         let send_time = start_timer!(|| "Spawning threads");
@@ -0,0 +1,121 @@
+//! This module defines the [`TokioCompat<T>`] wrapper type for using tokio
+//! network streams with the wrappers in this crate, and vice versa.
+//!
+//! The wrappers in this crate are built on the [`AsyncRead`]/[`AsyncWrite`]
+//! traits of the `futures` crate, while tokio defines its own
+//! [`tokio::io::AsyncRead`]/[`tokio::io::AsyncWrite`] traits. A
+//! [`TokioCompat<T>`] object implements whichever of the two trait families the
+//! object it wraps doesn't, so e.g. a [`tokio::net::TcpStream`] can be used in
+//! an [`IMuxAsync`] via [`IMuxAsync::new_tokio`], and a wrapper from this crate
+//! can be handed back to tokio code by wrapping it once more.
+//!
+//! [`CountingIO`] additionally implements the tokio traits directly.
+//!
+//! This module is only available with the `tokio` feature enabled.
+//!
+//! [`IMuxAsync`]: `crate::imux::IMuxAsync`
+//! [`IMuxAsync::new_tokio`]: `crate::imux::IMuxAsync::new_tokio`
+//! [`CountingIO`]: `crate::counting::CountingIO`
+//! [`tokio::net::TcpStream`]: https://docs.rs/tokio/1/tokio/net/struct.TcpStream.html
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures::{io, ready, AsyncRead, AsyncWrite};
+use tokio::io::ReadBuf;
+
+/// A wrapper type for converting between the tokio and `futures` IO traits.
+///
+/// `TokioCompat` implements [`AsyncRead`]/[`AsyncWrite`] if the wrapped object
+/// implements [`tokio::io::AsyncRead`]/[`tokio::io::AsyncWrite`], and the other
+/// way around.
+#[derive(Debug)]
+pub struct TokioCompat<T> {
+    inner: T,
+}
+
+impl<T> TokioCompat<T> {
+    /// Constructs a new `TokioCompat<T>` object.
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    /// Returns a reference to the wrapped object.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped object.
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consumes the wrapper and returns the wrapped object.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: tokio::io::AsyncRead + Unpin> AsyncRead for TokioCompat<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let mut buf = ReadBuf::new(buf);
+        ready!(tokio::io::AsyncRead::poll_read(
+            Pin::new(&mut self.get_mut().inner),
+            ctx,
+            &mut buf
+        ))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
+impl<T: tokio::io::AsyncWrite + Unpin> AsyncWrite for TokioCompat<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        tokio::io::AsyncWrite::poll_write(Pin::new(&mut self.get_mut().inner), ctx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        tokio::io::AsyncWrite::poll_flush(Pin::new(&mut self.get_mut().inner), ctx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        tokio::io::AsyncWrite::poll_shutdown(Pin::new(&mut self.get_mut().inner), ctx)
+    }
+}
+
+impl<T: AsyncRead + Unpin> tokio::io::AsyncRead for TokioCompat<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        let bytes =
+            ready!(Pin::new(&mut self.get_mut().inner).poll_read(ctx, buf.initialize_unfilled()))?;
+        buf.advance(bytes);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> tokio::io::AsyncWrite for TokioCompat<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(ctx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(ctx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(ctx)
+    }
+}
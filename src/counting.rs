@@ -5,31 +5,407 @@
 //! [`count-write`](https://crates.io/crates/count-write) crate.
 use core::pin::Pin;
 use core::task::{Context, Poll};
-use futures::{io, AsyncRead, AsyncWrite};
-use std::io::{Read, Write};
+use futures::{io, ready, AsyncBufRead, AsyncRead, AsyncWrite};
+use std::{
+    io::{BufRead, IoSlice, IoSliceMut, Read, Write},
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+mod budget;
+mod phase;
+mod stats;
+mod timing;
+use budget::BudgetState;
+pub use budget::{Budget, BudgetExceeded};
+use phase::PhaseTree;
+pub use phase::{PhaseGuard, PhaseReport};
+pub use stats::{CallStats, IoStats, HISTOGRAM_BUCKETS};
+use timing::Timing;
+pub use timing::{DirectionTiming, TimingStats};
+
+/// The direction of communication through a wrapped object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Data read from the wrapped object.
+    Read,
+    /// Data written to the wrapped object.
+    Write,
+}
 
 /// A wrapper type for measuring the amount of communication used by a network
 /// connection.
 ///
 /// `CountingIO` wraps an [`Read`]/[`Write`]/[`AsyncRead`]/[`AsyncWrite`]
-/// object and counts the number of bytes passed through it.
+/// object and counts the number of bytes passed through it. Bytes read and
+/// bytes written are counted separately so that a duplex stream can report
+/// upload and download independently.
+///
+/// `CountingIO` also counts the number of communication rounds, where a round
+/// is a maximal sequence of reads or of writes. A new round starts whenever
+/// data flows in the opposite direction from the previous transfer.
 ///
 /// `CountingIO` implements the [`Read`]/[`Write`]/[`AsyncRead`]/[`AsyncWrite`]
-/// traits itself, so a wrapped object can be used the same as before.
+/// traits itself, so a wrapped object can be used the same as before. This
+/// includes vectored reads and writes, as well as [`BufRead`]/[`AsyncBufRead`]
+/// for buffered objects, where bytes are counted once they are consumed. With
+/// the `tokio` feature enabled, `CountingIO` also implements tokio's
+/// `AsyncRead`/`AsyncWrite` traits.
+///
+/// By default the counters are owned by the wrapper and can only be accessed
+/// through it. A wrapper constructed with [`CountingIO::with_shared_counter`]
+/// instead uses atomic counters which can be read and reset from any thread
+/// through a [`CountHandle`], even after the wrapper has been moved elsewhere.
+///
+/// Communication can additionally be attributed to labelled, possibly nested,
+/// phases using [`CountingIO::phase`]:
+///
+/// ```
+/// # use io_utils::counting::CountingIO;
+/// # use std::io::Write;
+/// let mut io = CountingIO::new(Vec::new());
+/// {
+///     let _offline = io.phase("offline");
+///     io.write_all(&[0u8; 16]).unwrap();
+/// }
+/// let _online = io.phase("online");
+/// io.write_all(&[0u8; 4]).unwrap();
+///
+/// let phases = io.phases();
+/// assert_eq!(phases[0].label, "offline");
+/// assert_eq!(phases[0].bytes_written, 16);
+/// assert_eq!(phases[1].bytes_written, 4);
+/// ```
+///
+/// Per-call statistics, such as the number of calls and the distribution of
+/// their sizes, can be enabled with [`CountingIO::enable_stats`], and timing
+/// measurements, such as the time spent blocked in reads and writes, with
+/// [`CountingIO::enable_timing`].
+///
+/// A [`Budget`] set with [`CountingIO::set_budget`] limits the number of bytes
+/// which may be read and written, e.g. to assert the communication cost of a
+/// protocol in tests. Transfers beyond the budget fail with a
+/// [`BudgetExceeded`] error.
 pub struct CountingIO<I> {
     inner: I,
-    count: u64,
+    counter: Counter,
+    phases: Option<Arc<Mutex<PhaseTree>>>,
+    stats: Option<Box<IoStats>>,
+    timing: Option<Box<Timing>>,
+    budget: Option<Arc<BudgetState>>,
+}
+
+/// A cloneable handle to the counters of a [`CountingIO`] object constructed
+/// with [`CountingIO::with_shared_counter`].
+///
+/// A handle can be sent to other threads and used to sample or reset the
+/// counters while traffic is flowing through the wrapper.
+#[derive(Clone, Debug)]
+pub struct CountHandle {
+    counts: Arc<SharedCounts>,
+}
+
+/// The direction of the most recent transfer, used to detect new rounds.
+const NONE: u8 = 0;
+const READ: u8 = 1;
+const WRITE: u8 = 2;
+
+/// The storage backing the counters of a `CountingIO` object.
+enum Counter {
+    Local(LocalCounts),
+    Shared(Arc<SharedCounts>),
+}
+
+#[derive(Default)]
+struct LocalCounts {
+    read: u64,
+    written: u64,
+    rounds: u64,
+    last: u8,
+}
+
+#[derive(Debug, Default)]
+struct SharedCounts {
+    read: AtomicU64,
+    written: AtomicU64,
+    rounds: AtomicU64,
+    last: AtomicU8,
+}
+
+impl Counter {
+    fn read(&self) -> u64 {
+        match self {
+            Counter::Local(counts) => counts.read,
+            Counter::Shared(counts) => counts.read.load(Ordering::Relaxed),
+        }
+    }
+
+    fn written(&self) -> u64 {
+        match self {
+            Counter::Local(counts) => counts.written,
+            Counter::Shared(counts) => counts.written.load(Ordering::Relaxed),
+        }
+    }
+
+    fn rounds(&self) -> u64 {
+        match self {
+            Counter::Local(counts) => counts.rounds,
+            Counter::Shared(counts) => counts.rounds.load(Ordering::Relaxed),
+        }
+    }
+
+    fn add_read(&mut self, bytes: usize) {
+        match self {
+            Counter::Local(counts) => {
+                counts.read += bytes as u64;
+                if bytes > 0 && counts.last != READ {
+                    counts.last = READ;
+                    counts.rounds += 1;
+                }
+            }
+            Counter::Shared(counts) => {
+                counts.read.fetch_add(bytes as u64, Ordering::Relaxed);
+                if bytes > 0 && counts.last.swap(READ, Ordering::Relaxed) != READ {
+                    counts.rounds.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    fn add_written(&mut self, bytes: usize) {
+        match self {
+            Counter::Local(counts) => {
+                counts.written += bytes as u64;
+                if bytes > 0 && counts.last != WRITE {
+                    counts.last = WRITE;
+                    counts.rounds += 1;
+                }
+            }
+            Counter::Shared(counts) => {
+                counts.written.fetch_add(bytes as u64, Ordering::Relaxed);
+                if bytes > 0 && counts.last.swap(WRITE, Ordering::Relaxed) != WRITE {
+                    counts.rounds.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    fn reset_read(&mut self) {
+        match self {
+            Counter::Local(counts) => counts.read = 0,
+            Counter::Shared(counts) => counts.reset_read(),
+        }
+    }
+
+    fn reset_written(&mut self) {
+        match self {
+            Counter::Local(counts) => counts.written = 0,
+            Counter::Shared(counts) => counts.reset_written(),
+        }
+    }
+
+    fn reset_rounds(&mut self) {
+        match self {
+            Counter::Local(counts) => {
+                counts.rounds = 0;
+                counts.last = NONE;
+            }
+            Counter::Shared(counts) => counts.reset_rounds(),
+        }
+    }
+}
+
+impl SharedCounts {
+    fn reset_read(&self) {
+        self.read.store(0, Ordering::Relaxed);
+    }
+
+    fn reset_written(&self) {
+        self.written.store(0, Ordering::Relaxed);
+    }
+
+    fn reset_rounds(&self) {
+        self.rounds.store(0, Ordering::Relaxed);
+        self.last.store(NONE, Ordering::Relaxed);
+    }
 }
 
 impl<I> CountingIO<I> {
     /// Constructs a new `CountingIO<I>` object.
     pub fn new(inner: I) -> Self {
-        Self { inner, count: 0 }
+        Self {
+            inner,
+            counter: Counter::Local(LocalCounts::default()),
+            phases: None,
+            stats: None,
+            timing: None,
+            budget: None,
+        }
+    }
+
+    /// Constructs a new `CountingIO<I>` object whose counters can be accessed
+    /// from other threads through a [`CountHandle`].
+    pub fn with_shared_counter(inner: I) -> Self {
+        Self {
+            inner,
+            counter: Counter::Shared(Arc::new(SharedCounts::default())),
+            phases: None,
+            stats: None,
+            timing: None,
+            budget: None,
+        }
+    }
+
+    /// Returns a handle to the counters if this object was constructed with
+    /// [`CountingIO::with_shared_counter`].
+    pub fn handle(&self) -> Option<CountHandle> {
+        match &self.counter {
+            Counter::Local { .. } => None,
+            Counter::Shared(counts) => Some(CountHandle {
+                counts: counts.clone(),
+            }),
+        }
     }
 
     /// Returns the total communication amount in bytes.
     pub fn count(&self) -> u64 {
-        self.count
+        self.bytes_read() + self.bytes_written()
+    }
+
+    /// Returns the number of bytes read from the wrapped object.
+    pub fn bytes_read(&self) -> u64 {
+        self.counter.read()
+    }
+
+    /// Returns the number of bytes written to the wrapped object.
+    pub fn bytes_written(&self) -> u64 {
+        self.counter.written()
+    }
+
+    /// Returns the number of communication rounds.
+    pub fn rounds(&self) -> u64 {
+        self.counter.rounds()
+    }
+
+    /// Starts the phase `label`, nested inside the currently active phase if
+    /// there is one. All communication until the returned guard is dropped is
+    /// attributed to the phase.
+    ///
+    /// Entering a phase with the same label as an earlier phase at the same
+    /// position accumulates into the earlier phase.
+    pub fn phase(&mut self, label: &str) -> PhaseGuard {
+        let tree = self.phases.get_or_insert_with(Default::default);
+        PhaseGuard::new(tree, label)
+    }
+
+    /// Returns the communication breakdown of all phases entered so far.
+    pub fn phases(&self) -> Vec<PhaseReport> {
+        self.phases
+            .as_ref()
+            .map(|tree| tree.lock().unwrap().report())
+            .unwrap_or_default()
+    }
+
+    /// Clears all recorded phases. Phases which are currently active stay open
+    /// with their counts reset.
+    pub fn reset_phases(&mut self) {
+        if let Some(tree) = &self.phases {
+            tree.lock().unwrap().clear();
+        }
+    }
+
+    /// Starts recording statistics for every read and write call. Has no
+    /// effect if statistics are already being recorded.
+    pub fn enable_stats(&mut self) {
+        self.stats.get_or_insert_with(Default::default);
+    }
+
+    /// Returns a snapshot of the call statistics, or `None` if they haven't
+    /// been enabled with [`CountingIO::enable_stats`].
+    pub fn stats(&self) -> Option<IoStats> {
+        self.stats.as_deref().cloned()
+    }
+
+    /// Resets the call statistics.
+    pub fn reset_stats(&mut self) {
+        if let Some(stats) = &mut self.stats {
+            **stats = IoStats::default();
+        }
+    }
+
+    /// Starts recording timing measurements for every read and write call.
+    /// Has no effect if timing measurements are already being recorded.
+    pub fn enable_timing(&mut self) {
+        self.timing.get_or_insert_with(Default::default);
+    }
+
+    /// Returns a snapshot of the timing measurements, or `None` if they
+    /// haven't been enabled with [`CountingIO::enable_timing`].
+    pub fn timing(&self) -> Option<TimingStats> {
+        self.timing.as_ref().map(|t| t.stats.clone())
+    }
+
+    /// Resets the timing measurements.
+    pub fn reset_timing(&mut self) {
+        if let Some(timing) = &mut self.timing {
+            **timing = Timing::default();
+        }
+    }
+
+    /// Limits the number of bytes which may be transferred from now on to
+    /// `budget`, replacing any previous budget.
+    ///
+    /// A read or write which can only partially fit into the remaining budget
+    /// transfers as many bytes as fit, and once the budget of a direction is
+    /// used up, every further transfer in that direction fails with a
+    /// [`BudgetExceeded`] error.
+    pub fn set_budget(&mut self, budget: Budget) {
+        Self::share_budget(std::iter::once(self), budget);
+    }
+
+    /// Sets a single `budget` shared by all of `ios`, so that their combined
+    /// communication is limited. See [`CountingIO::set_budget`].
+    pub(crate) fn share_budget<'a>(ios: impl IntoIterator<Item = &'a mut Self>, budget: Budget)
+    where
+        I: 'a,
+    {
+        let state = Arc::new(BudgetState::new(budget));
+        ios.into_iter()
+            .for_each(|io| io.budget = Some(state.clone()));
+    }
+
+    /// Returns the number of bytes left in the budget, or `None` if no budget
+    /// has been set with [`CountingIO::set_budget`].
+    pub fn remaining_budget(&self) -> Option<Budget> {
+        self.budget.as_ref().map(|budget| budget.remaining())
+    }
+
+    /// Removes the budget, allowing unlimited communication again.
+    pub fn clear_budget(&mut self) {
+        self.budget = None;
+    }
+
+    fn record_read(&mut self, bytes: usize) {
+        self.counter.add_read(bytes);
+        if let Some(tree) = &self.phases {
+            tree.lock().unwrap().add_read(bytes);
+        }
+        if let Some(stats) = &mut self.stats {
+            stats.reads.record(bytes);
+        }
+    }
+
+    fn record_written(&mut self, bytes: usize) {
+        self.counter.add_written(bytes);
+        if let Some(tree) = &self.phases {
+            tree.lock().unwrap().add_written(bytes);
+        }
+        if let Some(stats) = &mut self.stats {
+            stats.writes.record(bytes);
+        }
     }
 
     /// Returns a reference to the wrapped object.
@@ -47,25 +423,262 @@ impl<I> CountingIO<I> {
         self.inner
     }
 
-    /// Resets the communication amount counter.
+    /// Resets the communication amount and round counters.
     pub fn reset(&mut self) {
-        self.count = 0;
+        self.reset_read();
+        self.reset_written();
+        self.reset_rounds();
+    }
+
+    /// Resets the counter for bytes read.
+    pub fn reset_read(&mut self) {
+        self.counter.reset_read();
+    }
+
+    /// Resets the counter for bytes written.
+    pub fn reset_written(&mut self) {
+        self.counter.reset_written();
+    }
+
+    /// Resets the round counter.
+    pub fn reset_rounds(&mut self) {
+        self.counter.reset_rounds();
     }
 }
 
+impl CountHandle {
+    /// Returns the total communication amount in bytes.
+    pub fn count(&self) -> u64 {
+        self.bytes_read() + self.bytes_written()
+    }
+
+    /// Returns the number of bytes read from the wrapped object.
+    pub fn bytes_read(&self) -> u64 {
+        self.counts.read.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes written to the wrapped object.
+    pub fn bytes_written(&self) -> u64 {
+        self.counts.written.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of communication rounds.
+    pub fn rounds(&self) -> u64 {
+        self.counts.rounds.load(Ordering::Relaxed)
+    }
+
+    /// Resets the communication amount and round counters.
+    pub fn reset(&self) {
+        self.reset_read();
+        self.reset_written();
+        self.reset_rounds();
+    }
+
+    /// Resets the counter for bytes read.
+    pub fn reset_read(&self) {
+        self.counts.reset_read();
+    }
+
+    /// Resets the counter for bytes written.
+    pub fn reset_written(&self) {
+        self.counts.reset_written();
+    }
+
+    /// Resets the round counter.
+    pub fn reset_rounds(&self) {
+        self.counts.reset_rounds();
+    }
+}
+
+/// Returns the number of bytes transferred by a completed poll, or `None` if
+/// the poll is still pending.
+fn poll_bytes(poll: &Poll<Result<usize, io::Error>>) -> Option<usize> {
+    match poll {
+        Poll::Pending => None,
+        Poll::Ready(Ok(bytes)) => Some(*bytes),
+        Poll::Ready(Err(_)) => Some(0),
+    }
+}
+
+/// Returns the first `len` bytes of the first non-empty buffer in `bufs`, for
+/// vectored writes of which only a part fits into the budget.
+fn first_buf<'a>(bufs: &'a [IoSlice<'_>], len: usize) -> &'a [u8] {
+    match bufs.iter().find(|buf| !buf.is_empty()) {
+        Some(buf) => &buf[..len.min(buf.len())],
+        None => &[],
+    }
+}
+
+/// Returns the first `len` bytes of the first non-empty buffer in `bufs`, for
+/// vectored reads of which only a part fits into the budget.
+fn first_buf_mut<'a>(bufs: &'a mut [IoSliceMut<'_>], len: usize) -> &'a mut [u8] {
+    match bufs.iter_mut().find(|buf| !buf.is_empty()) {
+        Some(buf) => {
+            let len = len.min(buf.len());
+            &mut buf[..len]
+        }
+        None => &mut [],
+    }
+}
+
+/// Truncates a buffer returned by `fill_buf` to the remaining read budget.
+fn limit_filled<'a>(budget: Option<&BudgetState>, buf: &'a [u8]) -> Result<&'a [u8], io::Error> {
+    match budget {
+        Some(budget) => Ok(&buf[..budget.available(Direction::Read, buf.len())?]),
+        None => Ok(buf),
+    }
+}
+
+impl<I> CountingIO<I> {
+    /// Reserves up to `len` bytes of the budget for a transfer in `direction`,
+    /// returning the number of bytes which may be transferred.
+    fn reserve(&self, direction: Direction, len: usize) -> Result<usize, io::Error> {
+        match &self.budget {
+            Some(budget) => budget.reserve(direction, len),
+            None => Ok(len),
+        }
+    }
+
+    /// Returns the `bytes` of a reservation which weren't transferred.
+    fn release(&self, direction: Direction, bytes: usize) {
+        if let Some(budget) = &self.budget {
+            budget.release(direction, bytes);
+        }
+    }
+
+    /// Performs a blocking read of at most `len` bytes on the wrapped object
+    /// and records it.
+    fn sync_read<F>(&mut self, len: usize, read: F) -> Result<usize, io::Error>
+    where
+        F: FnOnce(&mut I, usize) -> Result<usize, io::Error>,
+    {
+        let len = self.reserve(Direction::Read, len)?;
+        let start = self.timing.as_ref().map(|_| Instant::now());
+        let ret = read(&mut self.inner, len);
+        self.release(
+            Direction::Read,
+            len - ret.as_ref().map_or(0, |bytes| *bytes),
+        );
+        let bytes = ret?;
+        if let (Some(timing), Some(start)) = (&mut self.timing, start) {
+            timing.read(start, bytes);
+        }
+        self.record_read(bytes);
+        Ok(bytes)
+    }
+
+    /// Performs a blocking write of at most `len` bytes on the wrapped object
+    /// and records it.
+    fn sync_write<F>(&mut self, len: usize, write: F) -> Result<usize, io::Error>
+    where
+        F: FnOnce(&mut I, usize) -> Result<usize, io::Error>,
+    {
+        let len = self.reserve(Direction::Write, len)?;
+        let start = self.timing.as_ref().map(|_| Instant::now());
+        let ret = write(&mut self.inner, len);
+        self.release(
+            Direction::Write,
+            len - ret.as_ref().map_or(0, |bytes| *bytes),
+        );
+        let bytes = ret?;
+        if let (Some(timing), Some(start)) = (&mut self.timing, start) {
+            timing.write(start, bytes);
+        }
+        self.record_written(bytes);
+        Ok(bytes)
+    }
+
+    /// Polls a read of at most `len` bytes on the wrapped object and records
+    /// it once it completes.
+    fn async_read<F>(&mut self, len: usize, poll_read: F) -> Poll<Result<usize, io::Error>>
+    where
+        F: FnOnce(Pin<&mut I>, usize) -> Poll<Result<usize, io::Error>>,
+        I: Unpin,
+    {
+        let len = self.reserve(Direction::Read, len)?;
+        let start = self.timing.as_ref().map(|_| Instant::now());
+        let ret = poll_read(Pin::new(&mut self.inner), len);
+        self.release(Direction::Read, len - poll_bytes(&ret).unwrap_or(0));
+        if let (Some(timing), Some(start)) = (&mut self.timing, start) {
+            timing.poll_read(start, poll_bytes(&ret));
+        }
+        if let Poll::Ready(Ok(bytes)) = &ret {
+            self.record_read(*bytes);
+        }
+        ret
+    }
+
+    /// Polls a write of at most `len` bytes on the wrapped object and records
+    /// it once it completes.
+    fn async_write<F>(&mut self, len: usize, poll_write: F) -> Poll<Result<usize, io::Error>>
+    where
+        F: FnOnce(Pin<&mut I>, usize) -> Poll<Result<usize, io::Error>>,
+        I: Unpin,
+    {
+        let len = self.reserve(Direction::Write, len)?;
+        let start = self.timing.as_ref().map(|_| Instant::now());
+        let ret = poll_write(Pin::new(&mut self.inner), len);
+        self.release(Direction::Write, len - poll_bytes(&ret).unwrap_or(0));
+        if let (Some(timing), Some(start)) = (&mut self.timing, start) {
+            timing.poll_write(start, poll_bytes(&ret));
+        }
+        if let Poll::Ready(Ok(bytes)) = &ret {
+            self.record_written(*bytes);
+        }
+        ret
+    }
+}
+
+// `Write::is_write_vectored` and `Read::is_read_vectored` are not yet stable,
+// so the wrapped object's default implementations can't be forwarded.
+
 impl<I: Read> Read for CountingIO<I> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
-        let bytes = self.inner.read(buf)?;
-        self.count += bytes as u64;
-        Ok(bytes)
+        self.sync_read(buf.len(), |inner, len| inner.read(&mut buf[..len]))
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize, io::Error> {
+        let total = bufs.iter().map(|buf| buf.len()).sum();
+        self.sync_read(total, |inner, len| {
+            if len == total {
+                inner.read_vectored(bufs)
+            } else {
+                inner.read(first_buf_mut(bufs, len))
+            }
+        })
+    }
+}
+
+impl<I: BufRead> BufRead for CountingIO<I> {
+    fn fill_buf(&mut self) -> Result<&[u8], io::Error> {
+        let buf = self.inner.fill_buf()?;
+        limit_filled(self.budget.as_deref(), buf)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // Bytes returned by `fill_buf` are only counted once they're consumed
+        self.inner.consume(amt);
+        if let Some(budget) = &self.budget {
+            budget.charge(Direction::Read, amt);
+        }
+        self.record_read(amt);
     }
 }
 
 impl<I: Write> Write for CountingIO<I> {
     fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
-        let bytes = self.inner.write(buf)?;
-        self.count += bytes as u64;
-        Ok(bytes)
+        self.sync_write(buf.len(), |inner, len| inner.write(&buf[..len]))
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize, io::Error> {
+        let total = bufs.iter().map(|buf| buf.len()).sum();
+        self.sync_write(total, |inner, len| {
+            if len == total {
+                inner.write_vectored(bufs)
+            } else {
+                inner.write(first_buf(bufs, len))
+            }
+        })
     }
 
     fn flush(&mut self) -> Result<(), io::Error> {
@@ -79,13 +692,46 @@ impl<I: AsyncRead + Unpin> AsyncRead for CountingIO<I> {
         ctx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<Result<usize, io::Error>> {
-        let Self { inner, count } = self.get_mut();
-        let pin = Pin::new(inner);
-        let ret = pin.poll_read(ctx, buf);
-        if let Poll::Ready(Ok(read)) = &ret {
-            *count += *read as u64;
+        self.get_mut().async_read(buf.len(), |inner, len| {
+            inner.poll_read(ctx, &mut buf[..len])
+        })
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        let total = bufs.iter().map(|buf| buf.len()).sum();
+        self.get_mut().async_read(total, |inner, len| {
+            if len == total {
+                inner.poll_read_vectored(ctx, bufs)
+            } else {
+                inner.poll_read(ctx, first_buf_mut(bufs, len))
+            }
+        })
+    }
+}
+
+impl<I: AsyncBufRead + Unpin> AsyncBufRead for CountingIO<I> {
+    fn poll_fill_buf(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+    ) -> Poll<Result<&[u8], io::Error>> {
+        let this = self.get_mut();
+        let buf = ready!(Pin::new(&mut this.inner).poll_fill_buf(ctx))?;
+        Poll::Ready(limit_filled(this.budget.as_deref(), buf))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        // Bytes returned by `poll_fill_buf` are only counted once they're
+        // consumed
+        let this = self.get_mut();
+        Pin::new(&mut this.inner).consume(amt);
+        if let Some(budget) = &this.budget {
+            budget.charge(Direction::Read, amt);
         }
-        ret
+        this.record_read(amt);
     }
 }
 
@@ -95,13 +741,23 @@ impl<I: AsyncWrite + Unpin> AsyncWrite for CountingIO<I> {
         ctx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, io::Error>> {
-        let Self { inner, count } = self.get_mut();
-        let pin = Pin::new(inner);
-        let ret = pin.poll_write(ctx, buf);
-        if let Poll::Ready(Ok(written)) = &ret {
-            *count += *written as u64;
-        }
-        ret
+        self.get_mut()
+            .async_write(buf.len(), |inner, len| inner.poll_write(ctx, &buf[..len]))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        let total = bufs.iter().map(|buf| buf.len()).sum();
+        self.get_mut().async_write(total, |inner, len| {
+            if len == total {
+                inner.poll_write_vectored(ctx, bufs)
+            } else {
+                inner.poll_write(ctx, first_buf(bufs, len))
+            }
+        })
     }
 
     fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
@@ -112,3 +768,62 @@ impl<I: AsyncWrite + Unpin> AsyncWrite for CountingIO<I> {
         Pin::new(&mut self.get_mut().inner).poll_close(ctx)
     }
 }
+
+#[cfg(feature = "tokio")]
+impl<I: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for CountingIO<I> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        self.get_mut()
+            .async_read(buf.remaining(), |inner, len| {
+                let mut limited = tokio::io::ReadBuf::new(&mut buf.initialize_unfilled()[..len]);
+                ready!(tokio::io::AsyncRead::poll_read(inner, ctx, &mut limited))?;
+                let bytes = limited.filled().len();
+                buf.advance(bytes);
+                Poll::Ready(Ok(bytes))
+            })
+            .map_ok(|_| ())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<I: tokio::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for CountingIO<I> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        self.get_mut().async_write(buf.len(), |inner, len| {
+            tokio::io::AsyncWrite::poll_write(inner, ctx, &buf[..len])
+        })
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        let total = bufs.iter().map(|buf| buf.len()).sum();
+        self.get_mut().async_write(total, |inner, len| {
+            if len == total {
+                tokio::io::AsyncWrite::poll_write_vectored(inner, ctx, bufs)
+            } else {
+                tokio::io::AsyncWrite::poll_write(inner, ctx, first_buf(bufs, len))
+            }
+        })
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        tokio::io::AsyncWrite::is_write_vectored(&self.inner)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        tokio::io::AsyncWrite::poll_flush(Pin::new(&mut self.get_mut().inner), ctx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        tokio::io::AsyncWrite::poll_shutdown(Pin::new(&mut self.get_mut().inner), ctx)
+    }
+}
@@ -0,0 +1,151 @@
+use super::Direction;
+use futures::io;
+use std::{
+    error::Error,
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// A limit on the number of bytes transferred in each direction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Budget {
+    /// The maximum number of bytes which may be read, or `None` for no limit.
+    pub read: Option<u64>,
+    /// The maximum number of bytes which may be written, or `None` for no
+    /// limit.
+    pub write: Option<u64>,
+}
+
+/// The error returned by a read or write which would exceed a [`Budget`].
+///
+/// The error is returned wrapped in an [`io::Error`] of kind
+/// [`io::ErrorKind::Other`] and can be recovered with
+/// [`BudgetExceeded::from_io_error`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BudgetExceeded {
+    /// The direction whose budget was exceeded.
+    pub direction: Direction,
+    /// The budget for `direction` in bytes.
+    pub limit: u64,
+}
+
+impl BudgetExceeded {
+    /// Returns the `BudgetExceeded` error wrapped by `err`, or `None` if `err`
+    /// was caused by something else.
+    pub fn from_io_error(err: &io::Error) -> Option<&Self> {
+        err.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::Read => "read",
+            Direction::Write => "write",
+        };
+        write!(
+            f,
+            "Communication budget of {} bytes exceeded by {}",
+            self.limit, direction
+        )
+    }
+}
+
+impl Error for BudgetExceeded {}
+
+impl From<BudgetExceeded> for io::Error {
+    fn from(err: BudgetExceeded) -> Self {
+        io::Error::other(err)
+    }
+}
+
+/// The bytes spent against a [`Budget`], shared between all channels the
+/// budget applies to.
+#[derive(Debug)]
+pub(super) struct BudgetState {
+    limit: Budget,
+    read: AtomicU64,
+    written: AtomicU64,
+}
+
+impl BudgetState {
+    pub(super) fn new(limit: Budget) -> Self {
+        Self {
+            limit,
+            read: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+        }
+    }
+
+    fn parts(&self, direction: Direction) -> (Option<u64>, &AtomicU64) {
+        match direction {
+            Direction::Read => (self.limit.read, &self.read),
+            Direction::Write => (self.limit.write, &self.written),
+        }
+    }
+
+    /// Returns the number of bytes left in the budget.
+    pub(super) fn remaining(&self) -> Budget {
+        let remaining = |direction| {
+            let (limit, spent) = self.parts(direction);
+            limit.map(|limit| limit.saturating_sub(spent.load(Ordering::Relaxed)))
+        };
+        Budget {
+            read: remaining(Direction::Read),
+            write: remaining(Direction::Write),
+        }
+    }
+
+    /// Returns how many of `len` bytes fit into the budget, without reserving
+    /// them. Fails if `len` is non-zero and the budget is already used up.
+    pub(super) fn available(&self, direction: Direction, len: usize) -> Result<usize, io::Error> {
+        match self.parts(direction) {
+            (Some(limit), spent) if len > 0 => {
+                match limit
+                    .saturating_sub(spent.load(Ordering::Relaxed))
+                    .min(len as u64)
+                {
+                    0 => Err(BudgetExceeded { direction, limit }.into()),
+                    available => Ok(available as usize),
+                }
+            }
+            _ => Ok(len),
+        }
+    }
+
+    /// Reserves up to `len` bytes of the budget for a transfer, returning the
+    /// number of bytes reserved. Fails if `len` is non-zero and the budget is
+    /// already used up.
+    pub(super) fn reserve(&self, direction: Direction, len: usize) -> Result<usize, io::Error> {
+        let (limit, spent) = match self.parts(direction) {
+            (Some(limit), spent) => (limit, spent),
+            (None, _) => return Ok(len),
+        };
+        if len == 0 {
+            return Ok(0);
+        }
+        // Reserve atomically so concurrent channels can't overshoot the budget
+        let mut reserved = 0;
+        spent
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |spent| {
+                reserved = limit.saturating_sub(spent).min(len as u64);
+                Some(spent + reserved).filter(|_| reserved > 0)
+            })
+            .map_err(|_| BudgetExceeded { direction, limit })?;
+        Ok(reserved as usize)
+    }
+
+    /// Returns `bytes` reserved bytes which weren't transferred to the budget.
+    pub(super) fn release(&self, direction: Direction, bytes: usize) {
+        if let (Some(_), spent) = self.parts(direction) {
+            spent.fetch_sub(bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Charges `bytes` transferred bytes to the budget without checking it.
+    pub(super) fn charge(&self, direction: Direction, bytes: usize) {
+        if let (Some(_), spent) = self.parts(direction) {
+            spent.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+}
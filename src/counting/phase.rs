@@ -0,0 +1,210 @@
+//! Labelled phase accounting for the [`CountingIO`] wrapper.
+//!
+//! [`CountingIO`]: `super::CountingIO`
+
+use super::{NONE, READ, WRITE};
+use std::sync::{Arc, Mutex};
+
+/// A guard which attributes communication to a labelled phase until it is
+/// dropped.
+///
+/// Guards are returned by [`CountingIO::phase`] and don't borrow the wrapper,
+/// so the wrapper can still be used while the guard is alive. Phases opened
+/// while another phase is active are nested inside it.
+///
+/// [`CountingIO::phase`]: `super::CountingIO::phase`
+#[must_use = "the phase ends as soon as the guard is dropped"]
+pub struct PhaseGuard {
+    // The phase trees along with the depth of the phase in each tree
+    guards: Vec<(Arc<Mutex<PhaseTree>>, usize)>,
+}
+
+/// The communication attributed to a single phase.
+///
+/// The counts of a phase include the counts of all of its nested phases.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PhaseReport {
+    /// The label of the phase.
+    pub label: String,
+    /// The number of bytes read during the phase.
+    pub bytes_read: u64,
+    /// The number of bytes written during the phase.
+    pub bytes_written: u64,
+    /// The number of communication rounds during the phase.
+    pub rounds: u64,
+    /// The phases nested inside this phase.
+    pub children: Vec<PhaseReport>,
+}
+
+/// The phases of a single `CountingIO` object, stored as an arena of nodes.
+#[derive(Default)]
+pub(super) struct PhaseTree {
+    nodes: Vec<PhaseNode>,
+    roots: Vec<usize>,
+    stack: Vec<usize>,
+}
+
+struct PhaseNode {
+    label: String,
+    children: Vec<usize>,
+    read: u64,
+    written: u64,
+    rounds: u64,
+    last: u8,
+}
+
+impl PhaseGuard {
+    pub(super) fn new(tree: &Arc<Mutex<PhaseTree>>, label: &str) -> Self {
+        let depth = tree.lock().unwrap().enter(label);
+        Self {
+            guards: vec![(tree.clone(), depth)],
+        }
+    }
+
+    /// Combines several guards into a single guard which ends all of their
+    /// phases when dropped.
+    pub fn merge(guards: impl IntoIterator<Item = PhaseGuard>) -> Self {
+        Self {
+            guards: guards
+                .into_iter()
+                .flat_map(|mut g| std::mem::take(&mut g.guards))
+                .collect(),
+        }
+    }
+}
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        for (tree, depth) in self.guards.drain(..) {
+            if let Ok(mut tree) = tree.lock() {
+                tree.exit(depth);
+            }
+        }
+    }
+}
+
+impl PhaseReport {
+    /// Returns the total communication amount of the phase in bytes.
+    pub fn count(&self) -> u64 {
+        self.bytes_read + self.bytes_written
+    }
+
+    /// Combines the phase reports of several channels into a single report.
+    ///
+    /// Phases with the same label at the same position are merged by summing
+    /// their byte counts and taking the maximum of their round counts.
+    pub fn merge(reports: impl IntoIterator<Item = Vec<PhaseReport>>) -> Vec<PhaseReport> {
+        let mut merged: Vec<PhaseReport> = Vec::new();
+        for report in reports.into_iter().flatten() {
+            match merged.iter_mut().find(|r| r.label == report.label) {
+                Some(existing) => {
+                    existing.bytes_read += report.bytes_read;
+                    existing.bytes_written += report.bytes_written;
+                    existing.rounds = existing.rounds.max(report.rounds);
+                    let children = std::mem::take(&mut existing.children);
+                    existing.children = Self::merge(vec![children, report.children]);
+                }
+                None => merged.push(report),
+            }
+        }
+        merged
+    }
+}
+
+impl PhaseTree {
+    /// Opens the phase `label` nested inside the current phase, reusing an
+    /// existing node if the phase has been entered before. Returns the depth
+    /// of the new phase.
+    fn enter(&mut self, label: &str) -> usize {
+        let siblings = match self.stack.last() {
+            Some(&parent) => &self.nodes[parent].children,
+            None => &self.roots,
+        };
+        let existing = siblings
+            .iter()
+            .copied()
+            .find(|&i| self.nodes[i].label == label);
+        let node = match existing {
+            Some(node) => node,
+            None => {
+                let node = self.nodes.len();
+                self.nodes.push(PhaseNode {
+                    label: label.to_string(),
+                    children: Vec::new(),
+                    read: 0,
+                    written: 0,
+                    rounds: 0,
+                    last: NONE,
+                });
+                match self.stack.last() {
+                    Some(&parent) => self.nodes[parent].children.push(node),
+                    None => self.roots.push(node),
+                }
+                node
+            }
+        };
+        self.nodes[node].last = NONE;
+        self.stack.push(node);
+        self.stack.len() - 1
+    }
+
+    /// Closes the phase at `depth` along with any phases nested inside it.
+    fn exit(&mut self, depth: usize) {
+        self.stack.truncate(depth);
+    }
+
+    pub(super) fn add_read(&mut self, bytes: usize) {
+        self.add(READ, bytes);
+    }
+
+    pub(super) fn add_written(&mut self, bytes: usize) {
+        self.add(WRITE, bytes);
+    }
+
+    fn add(&mut self, direction: u8, bytes: usize) {
+        if bytes == 0 {
+            return;
+        }
+        for &i in &self.stack {
+            let node = &mut self.nodes[i];
+            if direction == READ {
+                node.read += bytes as u64;
+            } else {
+                node.written += bytes as u64;
+            }
+            if node.last != direction {
+                node.last = direction;
+                node.rounds += 1;
+            }
+        }
+    }
+
+    pub(super) fn report(&self) -> Vec<PhaseReport> {
+        self.roots.iter().map(|&i| self.report_node(i)).collect()
+    }
+
+    fn report_node(&self, i: usize) -> PhaseReport {
+        let node = &self.nodes[i];
+        PhaseReport {
+            label: node.label.clone(),
+            bytes_read: node.read,
+            bytes_written: node.written,
+            rounds: node.rounds,
+            children: node.children.iter().map(|&c| self.report_node(c)).collect(),
+        }
+    }
+
+    /// Clears all recorded phases. Phases which are currently active stay
+    /// open with their counts reset.
+    pub(super) fn clear(&mut self) {
+        let labels: Vec<String> = self
+            .stack
+            .iter()
+            .map(|&i| self.nodes[i].label.clone())
+            .collect();
+        *self = Self::default();
+        for label in &labels {
+            self.enter(label);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Call statistics for the [`CountingIO`] wrapper.
+//!
+//! [`CountingIO`]: `super::CountingIO`
+
+/// The number of buckets in a [`CallStats`] histogram.
+pub const HISTOGRAM_BUCKETS: usize = 65;
+
+/// A snapshot of the read and write call statistics of a [`CountingIO`]
+/// object.
+///
+/// [`CountingIO`]: `super::CountingIO`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IoStats {
+    /// Statistics for `read`/`poll_read` calls.
+    pub reads: CallStats,
+    /// Statistics for `write`/`poll_write` calls.
+    pub writes: CallStats,
+}
+
+/// Statistics for the successful calls made in a single direction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallStats {
+    /// The number of calls.
+    pub calls: u64,
+    /// The total number of bytes transferred by all calls.
+    pub bytes: u64,
+    /// The smallest number of bytes transferred by a single call.
+    pub min: u64,
+    /// The largest number of bytes transferred by a single call.
+    pub max: u64,
+    /// A log2-bucketed histogram of call sizes. Bucket `0` counts calls which
+    /// transferred no bytes and bucket `i > 0` counts calls which transferred
+    /// between `2^(i - 1)` and `2^i - 1` bytes.
+    pub histogram: [u64; HISTOGRAM_BUCKETS],
+}
+
+impl IoStats {
+    /// Adds the statistics in `other` to these statistics.
+    pub fn combine(&mut self, other: &IoStats) {
+        self.reads.combine(&other.reads);
+        self.writes.combine(&other.writes);
+    }
+}
+
+impl CallStats {
+    /// Returns the mean number of bytes transferred per call.
+    pub fn mean(&self) -> f64 {
+        if self.calls == 0 {
+            0.0
+        } else {
+            self.bytes as f64 / self.calls as f64
+        }
+    }
+
+    /// Returns the histogram bucket for a call transferring `size` bytes.
+    pub fn bucket(size: u64) -> usize {
+        (64 - size.leading_zeros()) as usize
+    }
+
+    /// Adds the statistics in `other` to these statistics.
+    pub fn combine(&mut self, other: &CallStats) {
+        if other.calls == 0 {
+            return;
+        }
+        self.min = if self.calls == 0 {
+            other.min
+        } else {
+            self.min.min(other.min)
+        };
+        self.max = self.max.max(other.max);
+        self.calls += other.calls;
+        self.bytes += other.bytes;
+        for (a, b) in self.histogram.iter_mut().zip(other.histogram.iter()) {
+            *a += b;
+        }
+    }
+
+    pub(super) fn record(&mut self, size: usize) {
+        let size = size as u64;
+        self.min = if self.calls == 0 {
+            size
+        } else {
+            self.min.min(size)
+        };
+        self.max = self.max.max(size);
+        self.calls += 1;
+        self.bytes += size;
+        self.histogram[Self::bucket(size)] += 1;
+    }
+}
+
+impl Default for CallStats {
+    fn default() -> Self {
+        Self {
+            calls: 0,
+            bytes: 0,
+            min: 0,
+            max: 0,
+            histogram: [0; HISTOGRAM_BUCKETS],
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Timing measurements for the [`CountingIO`] wrapper.
+//!
+//! [`CountingIO`]: `super::CountingIO`
+
+use std::time::{Duration, Instant};
+
+/// A snapshot of the timing measurements of a [`CountingIO`] object.
+///
+/// [`CountingIO`]: `super::CountingIO`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TimingStats {
+    /// Timing measurements for reads.
+    pub read: DirectionTiming,
+    /// Timing measurements for writes.
+    pub write: DirectionTiming,
+}
+
+/// Timing measurements for a single direction.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DirectionTiming {
+    /// The number of bytes transferred while timing was enabled.
+    pub bytes: u64,
+    /// The total time spent blocked in calls to the wrapped object.
+    ///
+    /// For [`Read`]/[`Write`] this is the duration of each call, and for
+    /// [`AsyncRead`]/[`AsyncWrite`] it is the time from the first poll that
+    /// returned [`Poll::Pending`] until the call completed.
+    ///
+    /// [`Read`]: `std::io::Read`
+    /// [`Write`]: `std::io::Write`
+    /// [`AsyncRead`]: `futures::AsyncRead`
+    /// [`AsyncWrite`]: `futures::AsyncWrite`
+    /// [`Poll::Pending`]: `core::task::Poll::Pending`
+    pub blocked: Duration,
+    /// The time at which the call transferring the first byte started, i.e.
+    /// the blocking call or the first poll of an async call.
+    pub first_byte: Option<Instant>,
+    /// The time at which the last byte was transferred.
+    pub last_byte: Option<Instant>,
+}
+
+impl TimingStats {
+    /// Combines the measurements in `other` into these measurements.
+    ///
+    /// Byte counts are summed and the first/last byte timestamps widened. The
+    /// blocked time is combined by taking the maximum, since the channels of an
+    /// inverse multiplexer block concurrently.
+    pub fn combine(&mut self, other: &TimingStats) {
+        self.read.combine(&other.read);
+        self.write.combine(&other.write);
+    }
+}
+
+impl DirectionTiming {
+    /// Returns the time from the start of the call transferring the first
+    /// byte until the last byte was transferred.
+    pub fn span(&self) -> Duration {
+        match (self.first_byte, self.last_byte) {
+            (Some(first), Some(last)) => last.duration_since(first),
+            _ => Duration::default(),
+        }
+    }
+
+    /// Returns the effective throughput in bytes per second, computed over the
+    /// [`span`](Self::span) rather than the blocked time, since reads served
+    /// from data which already arrived never block. Returns `None` if the span
+    /// is empty.
+    pub fn throughput(&self) -> Option<f64> {
+        let secs = self.span().as_secs_f64();
+        if secs > 0.0 {
+            Some(self.bytes as f64 / secs)
+        } else {
+            None
+        }
+    }
+
+    /// Combines the measurements in `other` into these measurements.
+    pub fn combine(&mut self, other: &DirectionTiming) {
+        self.bytes += other.bytes;
+        self.blocked = self.blocked.max(other.blocked);
+        self.first_byte = match (self.first_byte, other.first_byte) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.last_byte = self.last_byte.max(other.last_byte);
+    }
+}
+
+/// The timing state of a `CountingIO` object.
+#[derive(Default)]
+pub(super) struct Timing {
+    pub(super) stats: TimingStats,
+    read_pending: Option<Instant>,
+    write_pending: Option<Instant>,
+}
+
+impl Timing {
+    /// Records a blocking read which started at `start` and transferred
+    /// `bytes` bytes.
+    pub(super) fn read(&mut self, start: Instant, bytes: usize) {
+        let now = Instant::now();
+        self.stats.read.blocked += now - start;
+        Self::transferred(&mut self.stats.read, start, now, bytes);
+    }
+
+    /// Records a blocking write which started at `start` and transferred
+    /// `bytes` bytes.
+    pub(super) fn write(&mut self, start: Instant, bytes: usize) {
+        let now = Instant::now();
+        self.stats.write.blocked += now - start;
+        Self::transferred(&mut self.stats.write, start, now, bytes);
+    }
+
+    /// Records the result of polling a read which started at `start`, where
+    /// `bytes` is `None` if the poll is still pending.
+    pub(super) fn poll_read(&mut self, start: Instant, bytes: Option<usize>) {
+        Self::poll(&mut self.stats.read, &mut self.read_pending, start, bytes);
+    }
+
+    /// Records the result of polling a write which started at `start`, where
+    /// `bytes` is `None` if the poll is still pending.
+    pub(super) fn poll_write(&mut self, start: Instant, bytes: Option<usize>) {
+        Self::poll(&mut self.stats.write, &mut self.write_pending, start, bytes);
+    }
+
+    fn poll(
+        timing: &mut DirectionTiming,
+        pending: &mut Option<Instant>,
+        start: Instant,
+        bytes: Option<usize>,
+    ) {
+        let now = Instant::now();
+        match bytes {
+            None => {
+                pending.get_or_insert(start);
+            }
+            Some(bytes) => {
+                // A call which was pending started at its first poll
+                let start = match pending.take() {
+                    Some(first_poll) => {
+                        timing.blocked += now - first_poll;
+                        first_poll
+                    }
+                    None => start,
+                };
+                Self::transferred(timing, start, now, bytes);
+            }
+        }
+    }
+
+    fn transferred(timing: &mut DirectionTiming, start: Instant, now: Instant, bytes: usize) {
+        if bytes > 0 {
+            timing.bytes += bytes as u64;
+            timing.first_byte.get_or_insert(start);
+            timing.last_byte = Some(now);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! This module defines the [`FaultyIO<I>`] wrapper type for injecting faults
+//! into a network connection in order to test error handling.
+//!
+//! [`FaultyIO<I>`] follows a deterministic [`FaultSchedule`] which either
+//! injects a fault at specific read/write calls or injects faults at random
+//! with a fixed seed. This makes it possible to write reproducible tests which
+//! exercise the error paths of code using a network stream.
+
+use crate::rng::XorShift;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures::{io, AsyncRead, AsyncWrite};
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+};
+
+/// A fault which can be injected into a read or write call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Only transfer half of the buffer (but at least one byte), i.e. a short
+    /// read or a partial write.
+    Short,
+    /// Fail with [`io::ErrorKind::WouldBlock`]. The asynchronous traits
+    /// instead return [`Poll::Pending`] after waking the task.
+    WouldBlock,
+    /// Fail with [`io::ErrorKind::Interrupted`].
+    Interrupted,
+    /// Signal the end of the stream. Reads return `0` bytes for this and all
+    /// subsequent calls, while writes return `0` bytes for this call only.
+    Eof,
+    /// Fail with an error of the given kind.
+    Error(io::ErrorKind),
+}
+
+/// The schedule of faults injected by a [`FaultyIO`] object.
+///
+/// Read and write calls are numbered separately starting at `0`, and every
+/// call counts towards the numbering, including calls a fault was injected
+/// into. Faults scheduled for a specific call take precedence over random
+/// faults.
+#[derive(Default)]
+pub struct FaultSchedule {
+    reads: BTreeMap<u64, Fault>,
+    writes: BTreeMap<u64, Fault>,
+    random: Option<RandomFaults>,
+}
+
+struct RandomFaults {
+    rng: XorShift,
+    probability: f64,
+    faults: Vec<Fault>,
+}
+
+impl FaultSchedule {
+    /// Constructs an empty schedule which doesn't inject any faults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Constructs a schedule which injects a fault into each call with the
+    /// given `probability`. Each fault is chosen uniformly from `faults` using
+    /// a random number generator seeded with `seed`.
+    pub fn random(seed: u64, probability: f64, faults: &[Fault]) -> Self {
+        Self {
+            random: Some(RandomFaults {
+                rng: XorShift::new(seed),
+                probability,
+                faults: faults.to_vec(),
+            }),
+            ..Self::default()
+        }
+    }
+
+    /// Injects `fault` into the read call with index `call`.
+    pub fn on_read(mut self, call: u64, fault: Fault) -> Self {
+        self.reads.insert(call, fault);
+        self
+    }
+
+    /// Injects `fault` into the write call with index `call`.
+    pub fn on_write(mut self, call: u64, fault: Fault) -> Self {
+        self.writes.insert(call, fault);
+        self
+    }
+
+    fn next(&mut self, read: bool, call: u64) -> Option<Fault> {
+        let scheduled = if read {
+            self.reads.remove(&call)
+        } else {
+            self.writes.remove(&call)
+        };
+        scheduled.or_else(|| {
+            let random = self.random.as_mut()?;
+            if random.faults.is_empty() || random.rng.next_f64() >= random.probability {
+                return None;
+            }
+            let i = random.rng.below(random.faults.len() as u64) as usize;
+            Some(random.faults[i])
+        })
+    }
+}
+
+/// A wrapper type for injecting faults into a network connection.
+///
+/// `FaultyIO` implements the [`Read`]/[`Write`]/[`AsyncRead`]/[`AsyncWrite`]
+/// traits itself, so a wrapped object can be used the same as before. Calls
+/// without a fault are passed on to the wrapped object unchanged.
+pub struct FaultyIO<I> {
+    inner: I,
+    schedule: FaultSchedule,
+    reads: u64,
+    writes: u64,
+    injected: u64,
+    eof: bool,
+}
+
+/// What to do with a call after consulting the schedule.
+enum Action {
+    /// Pass the first `n` bytes of the buffer on to the wrapped object.
+    Forward(usize),
+    /// Return the given result without calling the wrapped object.
+    Return(Result<usize, io::Error>),
+    /// Wake the task and return [`Poll::Pending`].
+    Pending,
+}
+
+impl<I> FaultyIO<I> {
+    /// Constructs a new `FaultyIO<I>` object following `schedule`.
+    pub fn new(inner: I, schedule: FaultSchedule) -> Self {
+        Self {
+            inner,
+            schedule,
+            reads: 0,
+            writes: 0,
+            injected: 0,
+            eof: false,
+        }
+    }
+
+    /// Returns the number of faults injected so far.
+    pub fn faults_injected(&self) -> u64 {
+        self.injected
+    }
+
+    /// Returns a reference to the wrapped object.
+    pub fn inner(&self) -> &I {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped object.
+    pub fn inner_mut(&mut self) -> &mut I {
+        &mut self.inner
+    }
+
+    /// Consumes the wrapper and returns the wrapped object.
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+
+    fn next_read(&mut self, len: usize) -> Action {
+        let call = self.reads;
+        self.reads += 1;
+        if self.eof {
+            return Action::Return(Ok(0));
+        }
+        let fault = self.schedule.next(true, call);
+        if fault == Some(Fault::Eof) {
+            self.eof = true;
+        }
+        self.action(fault, len)
+    }
+
+    fn next_write(&mut self, len: usize) -> Action {
+        let call = self.writes;
+        self.writes += 1;
+        let fault = self.schedule.next(false, call);
+        self.action(fault, len)
+    }
+
+    fn action(&mut self, fault: Option<Fault>, len: usize) -> Action {
+        if fault.is_some() {
+            self.injected += 1;
+        }
+        match fault {
+            None => Action::Forward(len),
+            Some(Fault::Short) => Action::Forward(len.min(len / 2 + 1)),
+            Some(Fault::WouldBlock) => Action::Pending,
+            Some(Fault::Interrupted) => {
+                Action::Return(Err(io::Error::from(io::ErrorKind::Interrupted)))
+            }
+            Some(Fault::Eof) => Action::Return(Ok(0)),
+            Some(Fault::Error(kind)) => {
+                Action::Return(Err(io::Error::new(kind, "Fault injected by FaultyIO")))
+            }
+        }
+    }
+}
+
+impl<I: Read> Read for FaultyIO<I> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        match self.next_read(buf.len()) {
+            Action::Forward(n) => self.inner.read(&mut buf[..n]),
+            Action::Return(ret) => ret,
+            Action::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl<I: Write> Write for FaultyIO<I> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        match self.next_write(buf.len()) {
+            Action::Forward(n) => self.inner.write(&buf[..n]),
+            Action::Return(ret) => ret,
+            Action::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for FaultyIO<I> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+        match this.next_read(buf.len()) {
+            Action::Forward(n) => Pin::new(&mut this.inner).poll_read(ctx, &mut buf[..n]),
+            Action::Return(ret) => Poll::Ready(ret),
+            Action::Pending => {
+                ctx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for FaultyIO<I> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+        match this.next_write(buf.len()) {
+            Action::Forward(n) => Pin::new(&mut this.inner).poll_write(ctx, &buf[..n]),
+            Action::Return(ret) => Poll::Ready(ret),
+            Action::Pending => {
+                ctx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(ctx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(ctx)
+    }
+}
@@ -13,26 +13,90 @@
 //! sending/receiving rather than providing a streaming interface. As a result,
 //! they don't re-implement the [`Read`]/[`Write`]/[`AsyncRead`]/[`AsyncWrite`]
 //! traits of the objects they wrap and using them will require modification to
-//! existing codebases.
+//! existing codebases. Alternatively, an [`IMuxAsync<I>`] can be converted into
+//! an [`IMuxStream<I>`] which implements the [`AsyncRead`]/[`AsyncWrite`]
+//! traits by splitting the byte stream into sequence-numbered frames, and an
+//! [`IMuxSync<I>`] into an [`IMuxReader`] or [`IMuxWriter`] implementing the
+//! [`Read`]/[`Write`] traits using background threads.
+//!
+//! Messages are received into a newly allocated vector by `read`, or into a
+//! buffer provided by the caller by `read_into` and `read_into_vec`, which
+//! avoids allocating for every message.
 //!
 //! Both types are compatible with the [`CountingIO`] wrapper and expose the
-//! underlying [`count`] and [`reset`] functions.
+//! underlying [`count`], [`bytes_read`], [`bytes_written`], [`rounds`] and
+//! [`reset`] functions.
+//!
+//! By default each channel sends an equal share of a message. With the
+//! [`Schedule::Adaptive`] schedule of the [`IMuxConfig`], messages are instead
+//! split into many small chunks which are sent by whichever channel is ready,
+//! so that a single congested channel doesn't stall the whole message.
+//!
+//! The channels of an inverse multiplexer over TCP can be established with
+//! [`IMuxSync::connect`]/[`IMuxSync::accept`] and their async counterparts,
+//! which perform a handshake so that channels end up in the same order on
+//! both sides even if they arrive out of order. An [`IMuxListener`] serves
+//! many clients from one listener by yielding every session as its channels
+//! complete.
+//!
+//! Every message starts with a versioned header carrying the number of
+//! channels, the chunk size and a message id, so that two endpoints which
+//! disagree on their configuration fail with a descriptive error instead of
+//! corrupting data. Chunks can optionally be protected by CRC32 checksums, see
+//! [`IMuxConfig::checksums`].
+//!
+//! With the `serde` feature enabled, typed values can be sent and received
+//! with `send`/`recv`, which encode them with `bincode`. The `ark-serialize`
+//! feature adds `send_canonical`/`recv_canonical` for values implementing
+//! the `CanonicalSerialize`/`CanonicalDeserialize` traits of `ark-serialize`.
+//!
+//! A communication [`Budget`] can be set on all channels at once, e.g. to
+//! assert that a phase of a protocol sends at most a given number of bytes.
+//!
+//! With the `tokio` feature enabled, an [`IMuxAsync<I>`] can be constructed
+//! directly over tokio network streams using [`IMuxAsync::new_tokio`].
 //!
 //! [stack_overflow]: https://stackoverflow.com/questions/65731653/how-to-efficiently-send-large-files-across-a-single-network-connection
 //! [wikipedia]: https://en.wikipedia.org/wiki/Inverse_multiplexer
 //! [`count`]: `CountingIO::count`
+//! [`bytes_read`]: `CountingIO::bytes_read`
+//! [`bytes_written`]: `CountingIO::bytes_written`
+//! [`rounds`]: `CountingIO::rounds`
 //! [`reset`]: `CountingIO::reset`
 
-use crate::counting::CountingIO;
+#[cfg(feature = "tokio")]
+use crate::compat::TokioCompat;
+use crate::counting::{Budget, CountingIO, PhaseGuard, PhaseReport, TimingStats};
+use crc32fast::Hasher;
 use crossbeam_utils::thread;
 use futures::{io, prelude::*, stream::FuturesUnordered, AsyncRead, AsyncWrite};
+use message::Message;
 use std::{
-    cmp::max,
     io::{Read, Write},
+    iter::StepBy,
+    ops::Range,
+    sync::Mutex,
 };
+use wire::{checksum, verify_checksum, Header, CHECKSUM_LEN, HEADER_LEN};
 
-/// The default chunk size
-const MIN_CHUNK_SIZE: usize = 8192;
+mod adaptive;
+mod config;
+mod handshake;
+#[cfg(feature = "async-std")]
+mod listener;
+mod message;
+mod stream;
+mod sync_stream;
+#[cfg(any(feature = "serde", feature = "ark-serialize"))]
+mod typed;
+mod wire;
+pub use config::{IMuxConfig, Schedule};
+#[cfg(feature = "async-std")]
+pub use listener::IMuxListener;
+pub use stream::IMuxStream;
+pub use sync_stream::{IMuxReader, IMuxWriter};
+#[cfg(feature = "ark-serialize")]
+pub use typed::Compression;
 
 /// An inverse multiplexer for asynchronous network streams.
 ///
@@ -40,6 +104,13 @@ const MIN_CHUNK_SIZE: usize = 8192;
 /// thread for each stream.
 pub struct IMuxSync<I> {
     channels: Vec<I>,
+    config: IMuxConfig,
+    // The ids of the next messages received and sent
+    read_id: u64,
+    write_id: u64,
+    // A buffer per channel which received data passes through, reused across
+    // messages
+    scratch: Vec<Vec<u8>>,
 }
 
 /// An inverse multiplexer for asynchronous network streams.
@@ -48,12 +119,73 @@ pub struct IMuxSync<I> {
 /// thread.
 pub struct IMuxAsync<I> {
     channels: Vec<I>,
+    config: IMuxConfig,
+    // The ids of the next messages received and sent
+    read_id: u64,
+    write_id: u64,
+    // A buffer per channel which received data passes through, reused across
+    // messages
+    scratch: Vec<Vec<u8>>,
+}
+
+/// The communication amount of a single channel of an inverse multiplexer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChannelCount {
+    /// The number of bytes read from the channel.
+    pub bytes_read: u64,
+    /// The number of bytes written to the channel.
+    pub bytes_written: u64,
+}
+
+impl ChannelCount {
+    /// Returns the total communication amount of the channel in bytes.
+    pub fn count(&self) -> u64 {
+        self.bytes_read + self.bytes_written
+    }
+
+    /// Returns the ratio between the largest and the mean communication
+    /// amount of `counts`.
+    ///
+    /// A skew of `1.0` means communication is spread evenly across all
+    /// channels, while a skew equal to the number of channels means all
+    /// communication happened on a single channel. Returns `1.0` if there was
+    /// no communication.
+    pub fn skew(counts: &[ChannelCount]) -> f64 {
+        let total: u64 = counts.iter().map(ChannelCount::count).sum();
+        let max = counts.iter().map(ChannelCount::count).max().unwrap_or(0);
+        if total == 0 {
+            1.0
+        } else {
+            max as f64 * counts.len() as f64 / total as f64
+        }
+    }
+}
+
+impl<I> From<&CountingIO<I>> for ChannelCount {
+    fn from(io: &CountingIO<I>) -> Self {
+        Self {
+            bytes_read: io.bytes_read(),
+            bytes_written: io.bytes_written(),
+        }
+    }
 }
 
 impl<I> IMuxSync<I> {
     /// Constructs a new `IMuxSync<I>` object.
     pub fn new(channels: Vec<I>) -> Self {
-        Self { channels }
+        Self::with_config(channels, IMuxConfig::default())
+    }
+
+    /// Constructs a new `IMuxSync<I>` object which splits messages into
+    /// chunks according to `config`.
+    pub fn with_config(channels: Vec<I>, config: IMuxConfig) -> Self {
+        Self {
+            channels,
+            config,
+            read_id: 0,
+            write_id: 0,
+            scratch: Vec::new(),
+        }
     }
 
     /// Consumes the inverse multiplexer and returns the underlying streams.
@@ -71,18 +203,32 @@ impl<I> IMuxSync<I> {
         self.channels.iter_mut().collect()
     }
 
+    /// Returns the chunking configuration.
+    pub fn config(&self) -> &IMuxConfig {
+        &self.config
+    }
+
     fn chunk_size(&self, len: usize) -> usize {
-        max(
-            MIN_CHUNK_SIZE,
-            (len as f64 / self.channels.len() as f64).ceil() as usize,
-        )
+        self.config.chunk_size(len, self.channels.len())
     }
 }
 
 impl<I> IMuxAsync<I> {
     /// Constructs a new `IMuxAsync<I>` object.
     pub fn new(channels: Vec<I>) -> Self {
-        Self { channels }
+        Self::with_config(channels, IMuxConfig::default())
+    }
+
+    /// Constructs a new `IMuxAsync<I>` object which splits messages into
+    /// chunks according to `config`.
+    pub fn with_config(channels: Vec<I>, config: IMuxConfig) -> Self {
+        Self {
+            channels,
+            config,
+            read_id: 0,
+            write_id: 0,
+            scratch: Vec::new(),
+        }
     }
 
     /// Consumes the inverse multiplexer and returns the underlying streams.
@@ -100,11 +246,22 @@ impl<I> IMuxAsync<I> {
         self.channels.iter_mut().collect()
     }
 
+    /// Returns the chunking configuration.
+    pub fn config(&self) -> &IMuxConfig {
+        &self.config
+    }
+
     fn chunk_size(&self, len: usize) -> usize {
-        max(
-            MIN_CHUNK_SIZE,
-            (len as f64 / self.channels.len() as f64).ceil() as usize,
-        )
+        self.config.chunk_size(len, self.channels.len())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<I> IMuxAsync<TokioCompat<I>> {
+    /// Constructs a new `IMuxAsync<TokioCompat<I>>` object over tokio network
+    /// streams.
+    pub fn new_tokio(channels: Vec<I>) -> Self {
+        Self::new(channels.into_iter().map(TokioCompat::new).collect())
     }
 }
 
@@ -115,10 +272,116 @@ impl<I> IMuxSync<CountingIO<I>> {
         self.channels.iter().map(CountingIO::count).sum()
     }
 
-    /// Resets the communication amount counter.
+    /// Returns the number of bytes read by the inverse multiplexer.
+    pub fn bytes_read(&self) -> u64 {
+        self.channels.iter().map(CountingIO::bytes_read).sum()
+    }
+
+    /// Returns the number of bytes written by the inverse multiplexer.
+    pub fn bytes_written(&self) -> u64 {
+        self.channels.iter().map(CountingIO::bytes_written).sum()
+    }
+
+    /// Returns the number of bytes read and written on each channel.
+    pub fn per_channel_counts(&self) -> Vec<ChannelCount> {
+        self.channels.iter().map(ChannelCount::from).collect()
+    }
+
+    /// Returns how unevenly communication is spread across the channels. See
+    /// [`ChannelCount::skew`].
+    pub fn count_skew(&self) -> f64 {
+        ChannelCount::skew(&self.per_channel_counts())
+    }
+
+    /// Returns the number of communication rounds of the inverse multiplexer.
+    ///
+    /// Every message passes through the first channel, so this is the round
+    /// count of the busiest channel and a message spread across all channels
+    /// counts as a single round.
+    pub fn rounds(&self) -> u64 {
+        self.channels
+            .iter()
+            .map(CountingIO::rounds)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Starts the phase `label` on every channel. See [`CountingIO::phase`].
+    pub fn phase(&mut self, label: &str) -> PhaseGuard {
+        PhaseGuard::merge(self.channels.iter_mut().map(|c| c.phase(label)))
+    }
+
+    /// Returns the communication breakdown of all phases entered so far,
+    /// combined across all channels.
+    pub fn phases(&self) -> Vec<PhaseReport> {
+        PhaseReport::merge(self.channels.iter().map(CountingIO::phases))
+    }
+
+    /// Starts recording timing measurements on every channel. See
+    /// [`CountingIO::enable_timing`].
+    pub fn enable_timing(&mut self) {
+        self.channels.iter_mut().for_each(CountingIO::enable_timing);
+    }
+
+    /// Returns the timing measurements combined across all channels, or
+    /// `None` if they haven't been enabled. See [`TimingStats::combine`].
+    pub fn timing(&self) -> Option<TimingStats> {
+        self.channels.iter().map(CountingIO::timing).try_fold(
+            TimingStats::default(),
+            |mut acc, timing| {
+                acc.combine(&timing?);
+                Some(acc)
+            },
+        )
+    }
+
+    /// Resets the communication amount and round counters.
     pub fn reset(&mut self) {
         self.channels.iter_mut().for_each(CountingIO::reset);
     }
+
+    /// Resets the counter for bytes read.
+    pub fn reset_read(&mut self) {
+        self.channels.iter_mut().for_each(CountingIO::reset_read);
+    }
+
+    /// Resets the counter for bytes written.
+    pub fn reset_written(&mut self) {
+        self.channels.iter_mut().for_each(CountingIO::reset_written);
+    }
+
+    /// Resets the round counter.
+    pub fn reset_rounds(&mut self) {
+        self.channels.iter_mut().for_each(CountingIO::reset_rounds);
+    }
+
+    /// Clears all recorded phases.
+    pub fn reset_phases(&mut self) {
+        self.channels.iter_mut().for_each(CountingIO::reset_phases);
+    }
+
+    /// Resets the timing measurements.
+    pub fn reset_timing(&mut self) {
+        self.channels.iter_mut().for_each(CountingIO::reset_timing);
+    }
+
+    /// Limits the combined communication of all channels from now on to
+    /// `budget`, including the framing added by the inverse multiplexer. See
+    /// [`CountingIO::set_budget`].
+    pub fn set_budget(&mut self, budget: Budget) {
+        CountingIO::share_budget(&mut self.channels, budget);
+    }
+
+    /// Returns the number of bytes left in the budget, or `None` if no budget
+    /// has been set.
+    pub fn remaining_budget(&self) -> Option<Budget> {
+        self.channels.first()?.remaining_budget()
+    }
+
+    /// Removes the budget, allowing unlimited communication again.
+    pub fn clear_budget(&mut self) {
+        self.channels.iter_mut().for_each(CountingIO::clear_budget);
+    }
 }
 
 impl<I> IMuxAsync<CountingIO<I>> {
@@ -128,57 +391,361 @@ impl<I> IMuxAsync<CountingIO<I>> {
         self.channels.iter().map(CountingIO::count).sum()
     }
 
-    /// Resets the communication amount counter.
+    /// Returns the number of bytes read by the inverse multiplexer.
+    pub fn bytes_read(&self) -> u64 {
+        self.channels.iter().map(CountingIO::bytes_read).sum()
+    }
+
+    /// Returns the number of bytes written by the inverse multiplexer.
+    pub fn bytes_written(&self) -> u64 {
+        self.channels.iter().map(CountingIO::bytes_written).sum()
+    }
+
+    /// Returns the number of bytes read and written on each channel.
+    pub fn per_channel_counts(&self) -> Vec<ChannelCount> {
+        self.channels.iter().map(ChannelCount::from).collect()
+    }
+
+    /// Returns how unevenly communication is spread across the channels. See
+    /// [`ChannelCount::skew`].
+    pub fn count_skew(&self) -> f64 {
+        ChannelCount::skew(&self.per_channel_counts())
+    }
+
+    /// Returns the number of communication rounds of the inverse multiplexer.
+    ///
+    /// Every message passes through the first channel, so this is the round
+    /// count of the busiest channel and a message spread across all channels
+    /// counts as a single round.
+    pub fn rounds(&self) -> u64 {
+        self.channels
+            .iter()
+            .map(CountingIO::rounds)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Starts the phase `label` on every channel. See [`CountingIO::phase`].
+    pub fn phase(&mut self, label: &str) -> PhaseGuard {
+        PhaseGuard::merge(self.channels.iter_mut().map(|c| c.phase(label)))
+    }
+
+    /// Returns the communication breakdown of all phases entered so far,
+    /// combined across all channels.
+    pub fn phases(&self) -> Vec<PhaseReport> {
+        PhaseReport::merge(self.channels.iter().map(CountingIO::phases))
+    }
+
+    /// Starts recording timing measurements on every channel. See
+    /// [`CountingIO::enable_timing`].
+    pub fn enable_timing(&mut self) {
+        self.channels.iter_mut().for_each(CountingIO::enable_timing);
+    }
+
+    /// Returns the timing measurements combined across all channels, or
+    /// `None` if they haven't been enabled. See [`TimingStats::combine`].
+    pub fn timing(&self) -> Option<TimingStats> {
+        self.channels.iter().map(CountingIO::timing).try_fold(
+            TimingStats::default(),
+            |mut acc, timing| {
+                acc.combine(&timing?);
+                Some(acc)
+            },
+        )
+    }
+
+    /// Resets the communication amount and round counters.
     pub fn reset(&mut self) {
         self.channels.iter_mut().for_each(CountingIO::reset);
     }
+
+    /// Resets the counter for bytes read.
+    pub fn reset_read(&mut self) {
+        self.channels.iter_mut().for_each(CountingIO::reset_read);
+    }
+
+    /// Resets the counter for bytes written.
+    pub fn reset_written(&mut self) {
+        self.channels.iter_mut().for_each(CountingIO::reset_written);
+    }
+
+    /// Resets the round counter.
+    pub fn reset_rounds(&mut self) {
+        self.channels.iter_mut().for_each(CountingIO::reset_rounds);
+    }
+
+    /// Clears all recorded phases.
+    pub fn reset_phases(&mut self) {
+        self.channels.iter_mut().for_each(CountingIO::reset_phases);
+    }
+
+    /// Resets the timing measurements.
+    pub fn reset_timing(&mut self) {
+        self.channels.iter_mut().for_each(CountingIO::reset_timing);
+    }
+
+    /// Limits the combined communication of all channels from now on to
+    /// `budget`, including the framing added by the inverse multiplexer. See
+    /// [`CountingIO::set_budget`].
+    pub fn set_budget(&mut self, budget: Budget) {
+        CountingIO::share_budget(&mut self.channels, budget);
+    }
+
+    /// Returns the number of bytes left in the budget, or `None` if no budget
+    /// has been set.
+    pub fn remaining_budget(&self) -> Option<Budget> {
+        self.channels.first()?.remaining_budget()
+    }
+
+    /// Removes the budget, allowing unlimited communication again.
+    pub fn clear_budget(&mut self) {
+        self.channels.iter_mut().for_each(CountingIO::clear_budget);
+    }
+}
+
+/// The size of the buffer a message is first received into. The buffer is
+/// then doubled as the message arrives rather than allocated up front.
+const INITIAL_READ_SIZE: usize = 1 << 20;
+/// The number of bytes each channel receives at once when a message is
+/// received into a growing buffer.
+const BLOCK_SIZE: usize = 1 << 16;
+
+/// Distributes `chunks` across `channels` channels in turn, and returns the
+/// chunks of each channel.
+fn stripe<T>(chunks: impl Iterator<Item = T>, channels: usize) -> Vec<Vec<T>> {
+    let mut striped = (0..channels).map(|_| Vec::new()).collect::<Vec<_>>();
+    for (i, chunk) in chunks.enumerate() {
+        striped[i % channels].push(chunk);
+    }
+    striped
+}
+
+/// Returns the offsets of the chunks of a message of `len` bytes split into
+/// chunks of `chunk_size` bytes which channel `channel` of `channels` receives.
+/// The offsets are computed as they are needed, so the length claimed by the
+/// peer costs nothing up front.
+fn channel_offsets(
+    channel: usize,
+    channels: usize,
+    chunk_size: usize,
+    len: usize,
+) -> StepBy<Range<usize>> {
+    (channel.saturating_mul(chunk_size)..len).step_by(channels.saturating_mul(chunk_size))
+}
+
+/// Returns the length the buffer receiving a message of `len` bytes grows to
+/// once `received` bytes have arrived, so that a peer can't make us allocate
+/// much more memory than it has actually sent. Memory which has already been
+/// allocated is used up first.
+fn grow(received: usize, len: usize, capacity: usize) -> usize {
+    len.min(
+        received
+            .saturating_mul(2)
+            .max(INITIAL_READ_SIZE)
+            .max(capacity),
+    )
+}
+
+/// Returns the error for a message which doesn't fit the receiving buffer.
+fn length_mismatch(expected: usize, len: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Expected a message of {} bytes but received a message of {} bytes",
+            expected, len
+        ),
+    )
+}
+
+/// Receives the chunks at `offsets` of a message of `len` bytes split into
+/// chunks of `chunk_size` bytes from `reader` into `message`, a block at a
+/// time through `block`, checking the checksum after each chunk if
+/// `checksums` is set.
+fn read_blocks<R: Read>(
+    reader: &mut R,
+    block: &mut Vec<u8>,
+    offsets: StepBy<Range<usize>>,
+    chunk_size: usize,
+    len: usize,
+    checksums: bool,
+    message: &Mutex<Message<'_, Vec<u8>>>,
+) -> Result<(), io::Error> {
+    for offset in offsets {
+        let end = len.min(offset.saturating_add(chunk_size));
+        let mut hasher = Hasher::new();
+        for pos in (offset..end).step_by(BLOCK_SIZE) {
+            block.resize(BLOCK_SIZE.min(end - pos), 0);
+            reader.read_exact(block)?;
+            if checksums {
+                hasher.update(block);
+            }
+            message.lock().unwrap().store(pos, block);
+        }
+        if checksums {
+            let mut received = [0u8; CHECKSUM_LEN];
+            reader.read_exact(&mut received)?;
+            verify_checksum(hasher.finalize(), &received, offset)?;
+        }
+    }
+    Ok(())
 }
 
 impl<I: Read + Send> IMuxSync<I> {
     /// Receive a message over the inverse multiplexer.
     pub fn read(&mut self) -> Result<Vec<u8>, io::Error> {
-        // Read the length of the incoming buffer
-        let mut len_buf = [0u8; 8];
-        self.channels[0].read_exact(&mut len_buf)?;
-        let len: u64 = u64::from_le_bytes(len_buf);
+        let mut buf = Vec::new();
+        self.read_into_vec(&mut buf)?;
+        Ok(buf)
+    }
 
-        // Read the message in chunks
-        let mut buf = vec![0u8; len as usize];
+    /// Receive a message over the inverse multiplexer into `buf`, which must
+    /// have the same length as the message.
+    ///
+    /// If the lengths differ, an error is returned without reading the
+    /// message, which leaves the inverse multiplexer unusable.
+    pub fn read_into(&mut self, buf: &mut [u8]) -> Result<(), io::Error> {
+        let len = self.read_header()?;
+        if len != buf.len() {
+            return Err(length_mismatch(buf.len(), len));
+        }
+        let checksums = self.config.checksums_enabled();
+        match self.config.adaptive_chunk_size() {
+            Some(chunk_size) => {
+                self.scratch.resize_with(self.channels.len(), Vec::new);
+                let channels = &mut self.channels;
+                adaptive::read_sync(channels, &mut self.scratch, buf, len, chunk_size, checksums)
+            }
+            None => self.read_chunks(buf),
+        }
+    }
+
+    /// Receive a message over the inverse multiplexer into `buf`, replacing
+    /// its contents. The allocation of `buf` is reused, so receiving messages
+    /// no longer than earlier ones doesn't allocate.
+    pub fn read_into_vec(&mut self, buf: &mut Vec<u8>) -> Result<(), io::Error> {
+        let len = self.read_header()?;
+        buf.clear();
+        let checksums = self.config.checksums_enabled();
+        self.scratch.resize_with(self.channels.len(), Vec::new);
+        if let Some(chunk_size) = self.config.adaptive_chunk_size() {
+            let channels = &mut self.channels;
+            return adaptive::read_sync(
+                channels,
+                &mut self.scratch,
+                buf,
+                len,
+                chunk_size,
+                checksums,
+            );
+        }
+        // All channels receive their chunks at once while the buffer grows
+        let chunk_size = self.chunk_size(len);
+        let channels = self.channels.len();
+        let message = Mutex::new(Message::new(buf, len));
+        let shared = &message;
+        thread::scope(|s| {
+            self.channels
+                .iter_mut()
+                .zip(&mut self.scratch)
+                .enumerate()
+                .filter(|(i, _)| i.saturating_mul(chunk_size) < len)
+                .map(|(i, (reader, block))| {
+                    let offsets = channel_offsets(i, channels, chunk_size, len);
+                    s.spawn(move |_| {
+                        read_blocks(reader, block, offsets, chunk_size, len, checksums, shared)
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| io::Error::other(format!("Error occured while reading {:?}", e)))??;
+        message.into_inner().unwrap().finish()
+    }
+
+    /// Reads and checks the header of the incoming message, returning its
+    /// length.
+    fn read_header(&mut self) -> Result<usize, io::Error> {
+        let mut header = [0u8; HEADER_LEN];
+        self.channels[0].read_exact(&mut header)?;
+        let len =
+            Header::decode(&header)?.check(&self.config, self.channels.len(), self.read_id)?;
+        self.read_id += 1;
+        Ok(len)
+    }
+
+    /// Receives a message into `buf`, which has the length of the message.
+    fn read_chunks(&mut self, buf: &mut [u8]) -> Result<(), io::Error> {
         let chunk_size = self.chunk_size(buf.len());
+        let checksums = self.config.checksums_enabled();
+        let chunks = stripe(buf.chunks_mut(chunk_size).enumerate(), self.channels.len());
         thread::scope(|s| {
-            for (chunk, reader) in buf.chunks_mut(chunk_size).zip(self.channels.iter_mut()) {
-                s.spawn(move |_| reader.read_exact(chunk));
-            }
+            chunks
+                .into_iter()
+                .zip(self.channels.iter_mut())
+                .filter(|(chunks, _)| !chunks.is_empty())
+                .map(|(chunks, reader)| {
+                    s.spawn(move |_| {
+                        chunks.into_iter().try_for_each(|(i, chunk)| {
+                            reader.read_exact(chunk)?;
+                            if checksums {
+                                let mut received = [0u8; CHECKSUM_LEN];
+                                reader.read_exact(&mut received)?;
+                                verify_checksum(crc32fast::hash(chunk), &received, i * chunk_size)?;
+                            }
+                            Ok::<_, io::Error>(())
+                        })
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Result<Vec<_>, _>>()
         })
-        .map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("Error occured while reading {:?}", e),
-            )
-        })?;
-        Ok(buf)
+        .map_err(|e| io::Error::other(format!("Error occured while reading {:?}", e)))??;
+        Ok(())
     }
 }
 
 impl<I: Write + Send> IMuxSync<I> {
     /// Send a message over the inverse multiplexer.
     pub fn write(&mut self, buf: &[u8]) -> Result<(), io::Error> {
-        // Send the total buffer length
-        self.channels[0].write_all(&(buf.len() as u64).to_le_bytes())?;
+        // Send the message header
+        self.config.check_message_size(buf.len() as u64)?;
+        let header = Header::new(&self.config, self.channels.len(), self.write_id, buf.len());
+        self.channels[0].write_all(&header.encode())?;
+        self.write_id += 1;
 
         // Send `msg` in chunks
+        let checksums = self.config.checksums_enabled();
+        if let Some(chunk_size) = self.config.adaptive_chunk_size() {
+            return adaptive::write_sync(&mut self.channels, buf, chunk_size, checksums);
+        }
         let chunk_size = self.chunk_size(buf.len());
+        let chunks = stripe(buf.chunks(chunk_size), self.channels.len());
         thread::scope(|s| {
-            for (chunk, writer) in buf.chunks(chunk_size).zip(self.channels.iter_mut()) {
-                s.spawn(move |_| writer.write_all(chunk));
-            }
+            chunks
+                .into_iter()
+                .zip(self.channels.iter_mut())
+                .filter(|(chunks, _)| !chunks.is_empty())
+                .map(|(chunks, writer)| {
+                    s.spawn(move |_| {
+                        chunks.into_iter().try_for_each(|c| {
+                            writer.write_all(c)?;
+                            if checksums {
+                                writer.write_all(&checksum(c))?;
+                            }
+                            Ok::<_, io::Error>(())
+                        })
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Result<Vec<_>, _>>()
         })
-        .map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("Error occured while writing {:?}", e),
-            )
-        })?;
+        .map_err(|e| io::Error::other(format!("Error occured while writing {:?}", e)))??;
         Ok(())
     }
 
@@ -197,39 +764,146 @@ impl<I: Write + Send> IMuxSync<I> {
 impl<I: AsyncRead + Unpin> IMuxAsync<I> {
     /// Receive a message over the inverse multiplexer.
     pub async fn read(&mut self) -> Result<Vec<u8>, io::Error> {
-        // Read the length of the incoming buffer
-        let mut len_buf = [0u8; 8];
-        self.channels[0].read_exact(&mut len_buf).await?;
-        let len: u64 = u64::from_le_bytes(len_buf);
+        let mut buf = Vec::new();
+        self.read_into_vec(&mut buf).await?;
+        Ok(buf)
+    }
 
-        // Read the message in chunks
-        let mut buf = vec![0u8; len as usize];
+    /// Receive a message over the inverse multiplexer into `buf`, which must
+    /// have the same length as the message.
+    ///
+    /// If the lengths differ, an error is returned without reading the
+    /// message, which leaves the inverse multiplexer unusable.
+    pub async fn read_into(&mut self, buf: &mut [u8]) -> Result<(), io::Error> {
+        let len = self.read_header().await?;
+        if len != buf.len() {
+            return Err(length_mismatch(buf.len(), len));
+        }
+        let checksums = self.config.checksums_enabled();
+        match self.config.adaptive_chunk_size() {
+            Some(chunk_size) => {
+                self.scratch.resize_with(self.channels.len(), Vec::new);
+                let (channels, scratch) = (&mut self.channels, &mut self.scratch);
+                adaptive::read_async(channels, scratch, buf, len, chunk_size, checksums).await
+            }
+            None => self.read_chunks(buf).await,
+        }
+    }
+
+    /// Receive a message over the inverse multiplexer into `buf`, replacing
+    /// its contents. The allocation of `buf` is reused, so receiving messages
+    /// no longer than earlier ones doesn't allocate.
+    pub async fn read_into_vec(&mut self, buf: &mut Vec<u8>) -> Result<(), io::Error> {
+        let len = self.read_header().await?;
+        buf.clear();
+        let checksums = self.config.checksums_enabled();
+        self.scratch.resize_with(self.channels.len(), Vec::new);
+        if let Some(chunk_size) = self.config.adaptive_chunk_size() {
+            let (channels, scratch) = (&mut self.channels, &mut self.scratch);
+            return adaptive::read_async(channels, scratch, buf, len, chunk_size, checksums).await;
+        }
+        // All channels receive their chunks at once while the buffer grows
+        let chunk_size = self.chunk_size(len);
+        let channels = self.channels.len();
+        let message = Mutex::new(Message::new(buf, len));
+        let shared = &message;
+        self.channels
+            .iter_mut()
+            .zip(&mut self.scratch)
+            .enumerate()
+            .map(|(i, (r, block))| async move {
+                for offset in channel_offsets(i, channels, chunk_size, len) {
+                    let end = len.min(offset.saturating_add(chunk_size));
+                    let mut hasher = Hasher::new();
+                    for pos in (offset..end).step_by(BLOCK_SIZE) {
+                        block.resize(BLOCK_SIZE.min(end - pos), 0);
+                        r.read_exact(block).await?;
+                        if checksums {
+                            hasher.update(block);
+                        }
+                        shared.lock().unwrap().store(pos, block);
+                    }
+                    if checksums {
+                        let mut received = [0u8; CHECKSUM_LEN];
+                        r.read_exact(&mut received).await?;
+                        verify_checksum(hasher.finalize(), &received, offset)?;
+                    }
+                }
+                Ok::<_, io::Error>(())
+            })
+            .collect::<FuturesUnordered<_>>()
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        message.into_inner().unwrap().finish()
+    }
+
+    /// Reads and checks the header of the incoming message, returning its
+    /// length.
+    async fn read_header(&mut self) -> Result<usize, io::Error> {
+        let mut header = [0u8; HEADER_LEN];
+        self.channels[0].read_exact(&mut header).await?;
+        let len =
+            Header::decode(&header)?.check(&self.config, self.channels.len(), self.read_id)?;
+        self.read_id += 1;
+        Ok(len)
+    }
+
+    /// Receives a message into `buf`, which has the length of the message.
+    async fn read_chunks(&mut self, buf: &mut [u8]) -> Result<(), io::Error> {
         let chunk_size = self.chunk_size(buf.len());
-        buf.chunks_mut(chunk_size)
+        let checksums = self.config.checksums_enabled();
+        stripe(buf.chunks_mut(chunk_size).enumerate(), self.channels.len())
+            .into_iter()
             .zip(self.channels.iter_mut())
-            .map(|(chunk, r)| async move { r.read_exact(chunk).await })
+            .map(|(chunks, r)| async move {
+                for (i, chunk) in chunks {
+                    r.read_exact(chunk).await?;
+                    if checksums {
+                        let mut received = [0u8; CHECKSUM_LEN];
+                        r.read_exact(&mut received).await?;
+                        verify_checksum(crc32fast::hash(chunk), &received, i * chunk_size)?;
+                    }
+                }
+                Ok::<_, io::Error>(())
+            })
             .collect::<FuturesUnordered<_>>()
             .collect::<Vec<_>>()
             .await
             .into_iter()
             .collect::<Result<Vec<_>, _>>()?;
-        Ok(buf)
+        Ok(())
     }
 }
 
 impl<I: AsyncWrite + Unpin> IMuxAsync<I> {
     /// Send a message over the inverse multiplexer.
     pub async fn write(&mut self, buf: &[u8]) -> Result<(), io::Error> {
-        // Send the total buffer length
-        self.channels[0]
-            .write_all(&(buf.len() as u64).to_le_bytes())
-            .await?;
+        // Send the message header
+        self.config.check_message_size(buf.len() as u64)?;
+        let header = Header::new(&self.config, self.channels.len(), self.write_id, buf.len());
+        self.channels[0].write_all(&header.encode()).await?;
+        self.write_id += 1;
 
         // Send `msg` in chunks
+        let checksums = self.config.checksums_enabled();
+        if let Some(chunk_size) = self.config.adaptive_chunk_size() {
+            return adaptive::write_async(&mut self.channels, buf, chunk_size, checksums).await;
+        }
         let chunk_size = self.chunk_size(buf.len());
-        buf.chunks(chunk_size)
+        stripe(buf.chunks(chunk_size), self.channels.len())
+            .into_iter()
             .zip(self.channels.iter_mut())
-            .map(|(chunk, w)| async move { w.write_all(chunk).await })
+            .map(|(chunks, w)| async move {
+                for chunk in chunks {
+                    w.write_all(chunk).await?;
+                    if checksums {
+                        w.write_all(&checksum(chunk)).await?;
+                    }
+                }
+                Ok::<_, io::Error>(())
+            })
             .collect::<FuturesUnordered<_>>()
             .collect::<Vec<_>>()
             .await
@@ -0,0 +1,264 @@
+//! The adaptive schedule, which hands small chunks to whichever channel is
+//! ready to send them.
+//!
+//! Every chunk is preceded by a header holding its offset in the message as an
+//! 8 byte and its length as a 4 byte little-endian integer. Once a channel has
+//! no more chunks to send for a message, it sends a header with length `0`, so
+//! that the receiver knows when to stop reading from the channel. With
+//! checksums enabled, every chunk is followed by its checksum.
+//!
+//! The receiver checks that every chunk is one of the chunks of the message
+//! and that no chunk is received twice.
+
+use super::{
+    message::{Message, MessageBuf},
+    wire::{checksum, verify_checksum, CHECKSUM_LEN},
+};
+use futures::{io, prelude::*, stream::FuturesUnordered, AsyncRead, AsyncWrite};
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// The length of a chunk header.
+const HEADER_LEN: usize = 12;
+
+/// Encodes the header of a chunk of `len` bytes at `offset` in the message.
+fn encode_header(offset: usize, len: usize) -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    header[..8].copy_from_slice(&(offset as u64).to_le_bytes());
+    header[8..].copy_from_slice(&(len as u32).to_le_bytes());
+    header
+}
+
+/// Decodes a chunk header into the range of the message it covers, checking
+/// that it is one of the chunks of `chunk_size` bytes of a message of `total`
+/// bytes. Returns `None` for the end marker.
+fn decode_header(
+    header: &[u8; HEADER_LEN],
+    total: usize,
+    chunk_size: usize,
+) -> Result<Option<(usize, usize)>, io::Error> {
+    let mut offset = [0u8; 8];
+    offset.copy_from_slice(&header[..8]);
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&header[8..]);
+    let (offset, len) = (u64::from_le_bytes(offset), u32::from_le_bytes(len) as u64);
+    if len == 0 {
+        return Ok(None);
+    }
+    let valid = offset < total as u64
+        && offset % chunk_size as u64 == 0
+        && len == (total as u64 - offset).min(chunk_size as u64);
+    if !valid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Chunk at offset {} of length {} isn't one of the chunks of {} bytes of a \
+                 message of {} bytes",
+                offset, len, chunk_size, total
+            ),
+        ));
+    }
+    Ok(Some((offset as usize, len as usize)))
+}
+
+/// The message being received along with the indices of the chunks received
+/// so far, shared between the channels.
+struct Chunks<'a, B: ?Sized> {
+    message: Message<'a, B>,
+    received: HashSet<usize>,
+}
+
+impl<'a, B: MessageBuf + ?Sized> Chunks<'a, B> {
+    fn new(buf: &'a mut B, total: usize) -> Self {
+        Self {
+            message: Message::new(buf, total),
+            received: HashSet::new(),
+        }
+    }
+
+    /// Stores the chunk at `offset`, failing if it has been received before.
+    fn store(&mut self, offset: usize, chunk: &[u8], chunk_size: usize) -> Result<(), io::Error> {
+        if !self.received.insert(offset / chunk_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Received the chunk at offset {} twice", offset),
+            ));
+        }
+        self.message.store(offset, chunk);
+        Ok(())
+    }
+}
+
+/// Sends `buf` in chunks of `chunk_size` bytes, using a thread per channel.
+pub(super) fn write_sync<I: Write + Send>(
+    channels: &mut [I],
+    buf: &[u8],
+    chunk_size: usize,
+    checksums: bool,
+) -> Result<(), io::Error> {
+    let chunks = buf.chunks(chunk_size).collect::<Vec<_>>();
+    let next = AtomicUsize::new(0);
+    let (chunks, next) = (&chunks, &next);
+    crossbeam_utils::thread::scope(|s| {
+        channels
+            .iter_mut()
+            .map(|writer| {
+                s.spawn(move |_| {
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let chunk = match chunks.get(i) {
+                            Some(chunk) => chunk,
+                            None => break,
+                        };
+                        writer.write_all(&encode_header(i * chunk_size, chunk.len()))?;
+                        writer.write_all(chunk)?;
+                        if checksums {
+                            writer.write_all(&checksum(chunk))?;
+                        }
+                    }
+                    writer.write_all(&encode_header(0, 0))
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Result<Vec<_>, _>>()
+    })
+    .map_err(|e| io::Error::other(format!("Error occured while writing {:?}", e)))??;
+    Ok(())
+}
+
+/// Receives a message of `total` bytes split into chunks of `chunk_size` bytes
+/// into `buf`, which is either an empty vector or a slice of length `total`,
+/// using a thread per channel. Each channel receives its chunks into its
+/// buffer in `scratch` first.
+pub(super) fn read_sync<I: Read + Send, B: MessageBuf + ?Sized>(
+    channels: &mut [I],
+    scratch: &mut [Vec<u8>],
+    buf: &mut B,
+    total: usize,
+    chunk_size: usize,
+    checksums: bool,
+) -> Result<(), io::Error> {
+    let message = Mutex::new(Chunks::new(buf, total));
+    let shared = &message;
+    crossbeam_utils::thread::scope(|s| {
+        channels
+            .iter_mut()
+            .zip(scratch)
+            .map(|(reader, chunk)| {
+                s.spawn(move |_| {
+                    let mut header = [0u8; HEADER_LEN];
+                    loop {
+                        reader.read_exact(&mut header)?;
+                        let (offset, len) = match decode_header(&header, total, chunk_size)? {
+                            Some(range) => range,
+                            None => return Ok(()),
+                        };
+                        chunk.resize(len, 0);
+                        reader.read_exact(chunk)?;
+                        if checksums {
+                            let mut received = [0u8; CHECKSUM_LEN];
+                            reader.read_exact(&mut received)?;
+                            verify_checksum(crc32fast::hash(chunk), &received, offset)?;
+                        }
+                        shared.lock().unwrap().store(offset, chunk, chunk_size)?;
+                    }
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Result<Vec<_>, io::Error>>()
+    })
+    .map_err(|e| io::Error::other(format!("Error occured while reading {:?}", e)))??;
+    message.into_inner().unwrap().message.finish()
+}
+
+/// Sends `buf` in chunks of `chunk_size` bytes across all channels
+/// concurrently.
+pub(super) async fn write_async<I: AsyncWrite + Unpin>(
+    channels: &mut [I],
+    buf: &[u8],
+    chunk_size: usize,
+    checksums: bool,
+) -> Result<(), io::Error> {
+    let chunks = buf.chunks(chunk_size).collect::<Vec<_>>();
+    let next = AtomicUsize::new(0);
+    let (chunks, next) = (&chunks, &next);
+    // Each channel takes the next chunk as soon as it is done with its
+    // previous one, so faster channels send more chunks
+    channels
+        .iter_mut()
+        .map(|w| async move {
+            loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let chunk = match chunks.get(i) {
+                    Some(chunk) => chunk,
+                    None => break,
+                };
+                w.write_all(&encode_header(i * chunk_size, chunk.len()))
+                    .await?;
+                w.write_all(chunk).await?;
+                if checksums {
+                    w.write_all(&checksum(chunk)).await?;
+                }
+            }
+            w.write_all(&encode_header(0, 0)).await
+        })
+        .collect::<FuturesUnordered<_>>()
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(())
+}
+
+/// Receives a message of `total` bytes split into chunks of `chunk_size` bytes
+/// into `buf`, which is either an empty vector or a slice of length `total`,
+/// from all channels concurrently. Each channel receives its chunks into its
+/// buffer in `scratch` first.
+pub(super) async fn read_async<I: AsyncRead + Unpin, B: MessageBuf + ?Sized>(
+    channels: &mut [I],
+    scratch: &mut [Vec<u8>],
+    buf: &mut B,
+    total: usize,
+    chunk_size: usize,
+    checksums: bool,
+) -> Result<(), io::Error> {
+    let message = Mutex::new(Chunks::new(buf, total));
+    let shared = &message;
+    channels
+        .iter_mut()
+        .zip(scratch)
+        .map(|(r, chunk)| async move {
+            let mut header = [0u8; HEADER_LEN];
+            loop {
+                r.read_exact(&mut header).await?;
+                let (offset, len) = match decode_header(&header, total, chunk_size)? {
+                    Some(range) => range,
+                    None => return Ok(()),
+                };
+                chunk.resize(len, 0);
+                r.read_exact(chunk).await?;
+                if checksums {
+                    let mut received = [0u8; CHECKSUM_LEN];
+                    r.read_exact(&mut received).await?;
+                    verify_checksum(crc32fast::hash(chunk), &received, offset)?;
+                }
+                shared.lock().unwrap().store(offset, chunk, chunk_size)?;
+            }
+        })
+        .collect::<FuturesUnordered<_>>()
+        .collect::<Vec<Result<(), io::Error>>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    message.into_inner().unwrap().message.finish()
+}
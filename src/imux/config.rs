@@ -0,0 +1,185 @@
+//! The configuration of how an inverse multiplexer splits messages into
+//! chunks and assigns them to channels.
+
+use futures::io;
+use std::convert::TryFrom;
+
+/// The default minimum chunk size.
+const MIN_CHUNK_SIZE: usize = 8192;
+
+/// The configuration of how an inverse multiplexer splits messages into
+/// chunks.
+///
+/// A message is split into chunks of equal size, which are sent across the
+/// channels in turn. The chunk size is the message length divided by the
+/// number of channels, but at least the minimum chunk size and at most the
+/// maximum chunk size. Messages larger than the maximum chunk size times the
+/// number of channels are therefore sent in several rounds of chunks.
+///
+/// Messages smaller than the minimum chunk size times the number of channels
+/// are considered small, and are only split across a limited number of
+/// channels.
+///
+/// Alternatively, with the [`Schedule::Adaptive`] schedule, a message is split
+/// into many small chunks which are handed to whichever channel is ready to
+/// send them, so that a congested channel doesn't hold up the whole message.
+///
+/// Every message is preceded by a header describing how it is split, so that
+/// mismatched configurations or channel counts are detected rather than
+/// corrupting the message. Chunks can additionally be followed by a CRC32
+/// checksum of their contents to detect corruption in transit.
+///
+/// A maximum message size can be set to reject messages whose length prefix
+/// exceeds it, so that a faulty peer can't make the receiver allocate
+/// arbitrary amounts of memory.
+///
+/// Both endpoints of a connection must use the same configuration.
+///
+/// ```
+/// # use io_utils::imux::IMuxConfig;
+/// let config = IMuxConfig::new()
+///     .min_chunk_size(1 << 16)
+///     .max_chunk_size(1 << 22)
+///     .small_message_channels(1);
+/// assert_eq!(config.chunk_size(1 << 20, 4), 1 << 18);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IMuxConfig {
+    min_chunk_size: usize,
+    max_chunk_size: Option<usize>,
+    small_message_channels: Option<usize>,
+    max_message_size: Option<usize>,
+    checksums: bool,
+    schedule: Schedule,
+}
+
+/// How the chunks of a message are assigned to channels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Chunks are sent across the channels in turn, so each channel sends an
+    /// equal share of the message. This is the default.
+    Static,
+    /// The message is split into chunks of `chunk_size` bytes, each of which
+    /// is sent with its offset on whichever channel finishes its previous
+    /// chunk first. The receiver reassembles the message by offset, so
+    /// throughput tracks the fastest channels rather than the slowest.
+    ///
+    /// Each chunk carries a 12 byte header in addition to its checksum, if
+    /// enabled, and every channel sends an additional 12 byte marker at the
+    /// end of each message. The chunk size settings of the [`IMuxConfig`] are
+    /// ignored.
+    Adaptive {
+        /// The size of each chunk in bytes.
+        chunk_size: usize,
+    },
+}
+
+impl Default for IMuxConfig {
+    fn default() -> Self {
+        Self {
+            min_chunk_size: MIN_CHUNK_SIZE,
+            max_chunk_size: None,
+            small_message_channels: None,
+            max_message_size: None,
+            checksums: false,
+            schedule: Schedule::Static,
+        }
+    }
+}
+
+impl IMuxConfig {
+    /// Constructs the default configuration: the static schedule with a
+    /// minimum chunk size of 8KiB, no maximum chunk size, small messages split
+    /// across all channels, no maximum message size and no checksums.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the minimum chunk size in bytes.
+    pub fn min_chunk_size(mut self, size: usize) -> Self {
+        self.min_chunk_size = size.max(1);
+        self
+    }
+
+    /// Sets the maximum chunk size in bytes, which takes precedence over the
+    /// minimum chunk size.
+    pub fn max_chunk_size(mut self, size: usize) -> Self {
+        self.max_chunk_size = Some(size.max(1));
+        self
+    }
+
+    /// Sets the maximum number of channels a small message is split across.
+    pub fn small_message_channels(mut self, channels: usize) -> Self {
+        self.small_message_channels = Some(channels.max(1));
+        self
+    }
+
+    /// Sets the maximum size of a message in bytes. Sending a larger message
+    /// fails, as does receiving one.
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = Some(size);
+        self
+    }
+
+    /// Checks the length of a message against the maximum message size.
+    pub(super) fn check_message_size(&self, len: u64) -> Result<usize, io::Error> {
+        let max = self.max_message_size.unwrap_or(usize::MAX);
+        match usize::try_from(len) {
+            Ok(len) if len <= max => Ok(len),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Message of {} bytes exceeds the maximum message size of {} bytes",
+                    len, max
+                ),
+            )),
+        }
+    }
+
+    /// Enables or disables sending a CRC32 checksum after every chunk.
+    pub fn checksums(mut self, enabled: bool) -> Self {
+        self.checksums = enabled;
+        self
+    }
+
+    /// Returns whether chunks are followed by a checksum.
+    pub(super) fn checksums_enabled(&self) -> bool {
+        self.checksums
+    }
+
+    /// Sets the schedule assigning chunks to channels. The chunk size of an
+    /// adaptive schedule is clamped to between 1 byte and `u32::MAX` bytes,
+    /// the largest length a chunk header can hold.
+    pub fn schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = match schedule {
+            Schedule::Adaptive { chunk_size } => Schedule::Adaptive {
+                chunk_size: chunk_size.clamp(1, u32::MAX as usize),
+            },
+            Schedule::Static => Schedule::Static,
+        };
+        self
+    }
+
+    /// Returns the chunk size of the adaptive schedule, or `None` if the
+    /// static schedule is used.
+    pub(super) fn adaptive_chunk_size(&self) -> Option<usize> {
+        match self.schedule {
+            Schedule::Adaptive { chunk_size } => Some(chunk_size),
+            Schedule::Static => None,
+        }
+    }
+
+    /// Returns the size of the chunks a message of `len` bytes is split into
+    /// when sent across `channels` channels with the static schedule.
+    pub fn chunk_size(&self, len: usize, channels: usize) -> usize {
+        let channels = channels.max(1);
+        let channels = match self.small_message_channels {
+            Some(small) if len < self.min_chunk_size.saturating_mul(channels) => {
+                small.min(channels)
+            }
+            _ => channels,
+        };
+        let size = ((len as f64 / channels as f64).ceil() as usize).max(self.min_chunk_size);
+        self.max_chunk_size.map_or(size, |max| size.min(max))
+    }
+}
@@ -0,0 +1,521 @@
+//! Establishing the channels of an inverse multiplexer over TCP.
+//!
+//! Every channel starts with a handshake sent by the connecting side:
+//!
+//! | Bytes  | Field                                |
+//! |--------|--------------------------------------|
+//! | 0..4   | The magic bytes `IMXH`               |
+//! | 4      | The version of the handshake         |
+//! | 5..8   | Reserved                             |
+//! | 8..12  | The number of channels               |
+//! | 12..16 | The index of the channel             |
+//! | 16..24 | The session id                       |
+//!
+//! All integers are little-endian. The accepting side groups connections by
+//! session id and places each in the slot given by its index, so channels may
+//! arrive in any order. Once all channels of a session have connected, the
+//! accepting side acknowledges each of them by sending its handshake back,
+//! which the connecting side waits for.
+//!
+//! The accepting side reads the handshakes of all connections concurrently,
+//! so a connection which doesn't send one doesn't hold up the others.
+
+use super::{wire::invalid, IMuxSync};
+use futures::io;
+#[cfg(feature = "async-std")]
+use futures::{
+    future::{self, Either},
+    pin_mut,
+    stream::FuturesUnordered,
+    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt,
+};
+#[cfg(feature = "async-std")]
+use futures_timer::Delay;
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    convert::TryInto,
+    hash::{BuildHasher, Hasher},
+    io::{Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The magic bytes identifying a handshake.
+const MAGIC: [u8; 4] = *b"IMXH";
+/// The version of the handshake.
+const VERSION: u8 = 1;
+/// The length of a handshake.
+pub(super) const HELLO_LEN: usize = 24;
+/// The time a session has to connect all of its channels when accepted with
+/// `accept`, and the time the peer has to acknowledge them in `connect`.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// The time `IMuxSync::accept` waits for a handshake before checking for new
+/// connections.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The handshake identifying a channel.
+pub(super) struct Hello {
+    pub(super) session: u64,
+    pub(super) channels: usize,
+    pub(super) index: usize,
+}
+
+/// Returns an error unless `channels` is non-zero.
+pub(super) fn check_channels(channels: usize) -> Result<(), io::Error> {
+    if channels == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "An inverse multiplexer needs at least one channel",
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the error for a handshake which didn't arrive within `timeout`.
+fn handshake_timed_out(timeout: Duration) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("The peer didn't send a handshake within {:?}", timeout),
+    )
+}
+
+/// Checks the acknowledgement `ack` received for the channel identified by
+/// `hello`, where `received` is the result of reading it.
+fn check_ack(
+    received: Result<(), io::Error>,
+    ack: &[u8; HELLO_LEN],
+    hello: &Hello,
+) -> Result<(), io::Error> {
+    match received {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            format!(
+                "The peer closed channel {} of session {:016x} instead of accepting it",
+                hello.index, hello.session
+            ),
+        )),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "The peer didn't acknowledge channel {} of session {:016x} within {:?}",
+                    hello.index, hello.session, HANDSHAKE_TIMEOUT
+                ),
+            ))
+        }
+        Err(e) => Err(e),
+        Ok(()) if *ack != hello.encode() => Err(invalid(format!(
+            "Invalid acknowledgement of channel {} of session {:016x}",
+            hello.index, hello.session
+        ))),
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Generates a random session id.
+pub(super) fn session_id() -> u64 {
+    // `RandomState` is seeded randomly, so no further dependencies are needed
+    let mut hasher = RandomState::new().build_hasher();
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos());
+    hasher.write_u128(time);
+    hasher.write_u32(std::process::id());
+    hasher.finish()
+}
+
+impl Hello {
+    pub(super) fn encode(&self) -> [u8; HELLO_LEN] {
+        let mut hello = [0u8; HELLO_LEN];
+        hello[..4].copy_from_slice(&MAGIC);
+        hello[4] = VERSION;
+        hello[8..12].copy_from_slice(&(self.channels as u32).to_le_bytes());
+        hello[12..16].copy_from_slice(&(self.index as u32).to_le_bytes());
+        hello[16..24].copy_from_slice(&self.session.to_le_bytes());
+        hello
+    }
+
+    /// Decodes a handshake, checking its magic bytes, version and channel
+    /// index.
+    pub(super) fn decode(hello: &[u8; HELLO_LEN]) -> Result<Self, io::Error> {
+        if hello[..4] != MAGIC {
+            return Err(invalid(format!(
+                "Invalid handshake {:?}: the peer isn't an inverse multiplexer",
+                &hello[..4]
+            )));
+        }
+        if hello[4] != VERSION {
+            return Err(invalid(format!(
+                "The peer uses version {} of the handshake but version {} is supported",
+                hello[4], VERSION
+            )));
+        }
+        let channels = u32::from_le_bytes(hello[8..12].try_into().unwrap()) as usize;
+        let index = u32::from_le_bytes(hello[12..16].try_into().unwrap()) as usize;
+        if index >= channels {
+            return Err(invalid(format!(
+                "Invalid index {} of a channel out of {}",
+                index, channels
+            )));
+        }
+        Ok(Self {
+            session: u64::from_le_bytes(hello[16..24].try_into().unwrap()),
+            channels,
+            index,
+        })
+    }
+}
+
+/// The channels of the sessions which haven't been fully established yet.
+pub(super) struct Sessions<I> {
+    channels: usize,
+    pending: HashMap<u64, Pending<I>>,
+}
+
+/// The channels of a session connected so far.
+struct Pending<I> {
+    started: Instant,
+    slots: Vec<Option<I>>,
+}
+
+impl<I> Sessions<I> {
+    pub(super) fn new(channels: usize) -> Self {
+        Self {
+            channels,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Adds the channel `stream` identified by `hello`, and returns the
+    /// channels of its session in order once all of them have connected.
+    pub(super) fn add(&mut self, hello: Hello, stream: I) -> Result<Option<Vec<I>>, io::Error> {
+        if hello.channels != self.channels {
+            return Err(invalid(format!(
+                "The peer connects {} channels but {} channels are expected",
+                hello.channels, self.channels
+            )));
+        }
+        let channels = self.channels;
+        let slots = &mut self
+            .pending
+            .entry(hello.session)
+            .or_insert_with(|| Pending {
+                started: Instant::now(),
+                slots: (0..channels).map(|_| None).collect(),
+            })
+            .slots;
+        if slots[hello.index].is_some() {
+            return Err(invalid(format!(
+                "Channel {} of session {:016x} connected twice",
+                hello.index, hello.session
+            )));
+        }
+        slots[hello.index] = Some(stream);
+        if slots.iter().any(Option::is_none) {
+            return Ok(None);
+        }
+        let slots = self.pending.remove(&hello.session).unwrap().slots;
+        Ok(Some(slots.into_iter().map(Option::unwrap).collect()))
+    }
+
+    /// Returns the time at which the oldest session times out after
+    /// `timeout`, or `None` if there are no pending sessions.
+    #[cfg(feature = "async-std")]
+    pub(super) fn deadline(&self, timeout: Duration) -> Option<Instant> {
+        self.pending
+            .values()
+            .map(|pending| pending.started + timeout)
+            .min()
+    }
+
+    /// Removes a session which has been pending for longer than `timeout`,
+    /// closing its channels, and returns an error describing it.
+    pub(super) fn expire(&mut self, timeout: Duration) -> Option<io::Error> {
+        let now = Instant::now();
+        let (&session, pending) = self
+            .pending
+            .iter()
+            .find(|(_, pending)| pending.started + timeout <= now)?;
+        let connected = pending.slots.iter().filter(|slot| slot.is_some()).count();
+        self.pending.remove(&session);
+        Some(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "Session {:016x} timed out with {} of {} channels connected",
+                session, connected, self.channels
+            ),
+        ))
+    }
+}
+
+/// Returns the handshakes of the `channels` channels of `session`, which are
+/// sent back to acknowledge them.
+fn acks(session: u64, channels: usize) -> impl Iterator<Item = [u8; HELLO_LEN]> {
+    (0..channels).map(move |index| {
+        Hello {
+            session,
+            channels,
+            index,
+        }
+        .encode()
+    })
+}
+
+/// Reads the handshake of `stream`, failing if it doesn't arrive within
+/// `timeout`.
+fn read_hello_sync(stream: &mut TcpStream, timeout: Duration) -> Result<Hello, io::Error> {
+    let mut hello = [0u8; HELLO_LEN];
+    stream.set_read_timeout(Some(timeout))?;
+    stream.read_exact(&mut hello).map_err(|e| match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => handshake_timed_out(timeout),
+        _ => e,
+    })?;
+    stream.set_read_timeout(None)?;
+    Hello::decode(&hello)
+}
+
+/// Acknowledges the channels `streams` of `session`.
+fn acknowledge_sync(streams: &mut [TcpStream], session: u64) -> Result<(), io::Error> {
+    let acks = acks(session, streams.len());
+    for (stream, ack) in streams.iter_mut().zip(acks) {
+        stream.write_all(&ack)?;
+    }
+    Ok(())
+}
+
+/// Reads the handshake of `stream`, failing if it doesn't arrive within
+/// `timeout`.
+#[cfg(feature = "async-std")]
+pub(super) async fn read_hello_async<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    timeout: Duration,
+) -> Result<Hello, io::Error> {
+    let mut hello = [0u8; HELLO_LEN];
+    match future::select(stream.read_exact(&mut hello), Delay::new(timeout)).await {
+        Either::Left((result, _)) => result?,
+        Either::Right(_) => return Err(handshake_timed_out(timeout)),
+    }
+    Hello::decode(&hello)
+}
+
+/// Acknowledges the channels `streams` of `session`.
+#[cfg(feature = "async-std")]
+pub(super) async fn acknowledge_async<S: AsyncWrite + Unpin>(
+    streams: &mut [S],
+    session: u64,
+) -> Result<(), io::Error> {
+    let acks = acks(session, streams.len());
+    for (stream, ack) in streams.iter_mut().zip(acks) {
+        stream.write_all(&ack).await?;
+    }
+    Ok(())
+}
+
+/// Accepts connections on the non-blocking `listener` until a session of
+/// `channels` channels has connected and been acknowledged, and returns its
+/// channels.
+fn accept_sync(listener: &TcpListener, channels: usize) -> Result<Vec<TcpStream>, io::Error> {
+    let mut sessions = Sessions::new(channels);
+    let (sender, receiver) = mpsc::channel();
+    loop {
+        // Start reading the handshakes of new connections
+        loop {
+            match listener.accept() {
+                Ok((mut stream, _)) => {
+                    let sender = sender.clone();
+                    thread::spawn(move || {
+                        let hello = stream
+                            .set_nonblocking(false)
+                            .and_then(|()| read_hello_sync(&mut stream, HANDSHAKE_TIMEOUT));
+                        // The session may have been accepted already
+                        let _ = sender.send((hello, stream));
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        while sessions.expire(HANDSHAKE_TIMEOUT).is_some() {}
+        // Connections with an invalid handshake are closed
+        let (hello, stream) = match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok((Ok(hello), stream)) => (hello, stream),
+            _ => continue,
+        };
+        let session = hello.session;
+        if let Ok(Some(mut streams)) = sessions.add(hello, stream) {
+            if acknowledge_sync(&mut streams, session).is_ok() {
+                return Ok(streams);
+            }
+        }
+    }
+}
+
+impl IMuxSync<TcpStream> {
+    /// Connects `channels` channels to an inverse multiplexer accepting
+    /// connections at `addr` with [`IMuxSync::accept`] or
+    /// [`IMuxAsync::accept`](super::IMuxAsync::accept).
+    ///
+    /// Each channel starts with a handshake carrying a random session id and
+    /// the index of the channel, so the peer places it in the right slot even
+    /// if connections arrive out of order. Returns once the peer has
+    /// acknowledged all channels, and fails if it closes them instead, e.g.
+    /// because it accepted another session, or doesn't acknowledge them within
+    /// 10 seconds.
+    pub fn connect<A: ToSocketAddrs>(addr: A, channels: usize) -> Result<Self, io::Error> {
+        check_channels(channels)?;
+        let addrs = addr.to_socket_addrs()?.collect::<Vec<_>>();
+        let session = session_id();
+        let hellos = (0..channels).map(|index| Hello {
+            session,
+            channels,
+            index,
+        });
+        let mut streams = hellos
+            .map(|hello| {
+                let mut stream = TcpStream::connect(&addrs[..])?;
+                stream.write_all(&hello.encode())?;
+                Ok((hello, stream))
+            })
+            .collect::<Result<Vec<_>, io::Error>>()?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        for (hello, stream) in &mut streams {
+            let mut ack = [0u8; HELLO_LEN];
+            // A zero timeout is rejected, so wait at least a millisecond
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+            check_ack(stream.read_exact(&mut ack), &ack, hello)?;
+            stream.set_read_timeout(None)?;
+        }
+        Ok(Self::new(
+            streams.into_iter().map(|(_, stream)| stream).collect(),
+        ))
+    }
+
+    /// Accepts the `channels` channels of an inverse multiplexer connecting
+    /// with [`IMuxSync::connect`] or
+    /// [`IMuxAsync::connect`](super::IMuxAsync::connect) on `listener`.
+    ///
+    /// Connections are grouped by the session id of their handshake, and the
+    /// first session to connect all of its channels is acknowledged and
+    /// returned. Handshakes are read on a thread per connection, so a
+    /// connection which doesn't send one doesn't hold up the others.
+    /// Connections which don't send a valid handshake for `channels` channels
+    /// and sessions which don't connect all of their channels within 10
+    /// seconds are closed, as are the connections of other sessions once one
+    /// has been accepted.
+    ///
+    /// The listener is switched to non-blocking mode while accepting and back
+    /// to blocking mode before returning.
+    pub fn accept(listener: &TcpListener, channels: usize) -> Result<Self, io::Error> {
+        check_channels(channels)?;
+        listener.set_nonblocking(true)?;
+        let result = accept_sync(listener, channels);
+        listener.set_nonblocking(false)?;
+        result.map(Self::new)
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl super::IMuxAsync<async_std::net::TcpStream> {
+    /// Connects `channels` channels to an inverse multiplexer accepting
+    /// connections at `addr` with [`IMuxAsync::accept`](Self::accept) or
+    /// [`IMuxSync::accept`].
+    ///
+    /// Each channel starts with a handshake carrying a random session id and
+    /// the index of the channel, so the peer places it in the right slot even
+    /// if connections arrive out of order. Returns once the peer has
+    /// acknowledged all channels, and fails if it closes them instead, e.g.
+    /// because it accepted another session, or doesn't acknowledge them within
+    /// 10 seconds.
+    pub async fn connect<A: async_std::net::ToSocketAddrs>(
+        addr: A,
+        channels: usize,
+    ) -> Result<Self, io::Error> {
+        check_channels(channels)?;
+        let addrs = addr.to_socket_addrs().await?.collect::<Vec<_>>();
+        let session = session_id();
+        let mut streams = Vec::with_capacity(channels);
+        for index in 0..channels {
+            let hello = Hello {
+                session,
+                channels,
+                index,
+            };
+            let mut stream = async_std::net::TcpStream::connect(&addrs[..]).await?;
+            stream.write_all(&hello.encode()).await?;
+            streams.push((hello, stream));
+        }
+        let mut deadline = Delay::new(HANDSHAKE_TIMEOUT);
+        for (hello, stream) in &mut streams {
+            let mut ack = [0u8; HELLO_LEN];
+            let received = match future::select(stream.read_exact(&mut ack), &mut deadline).await {
+                Either::Left((received, _)) => received,
+                Either::Right(_) => Err(io::ErrorKind::TimedOut.into()),
+            };
+            check_ack(received, &ack, hello)?;
+        }
+        Ok(Self::new(
+            streams.into_iter().map(|(_, stream)| stream).collect(),
+        ))
+    }
+
+    /// Accepts the `channels` channels of an inverse multiplexer connecting
+    /// with [`IMuxAsync::connect`](Self::connect) or [`IMuxSync::connect`] on
+    /// `listener`.
+    ///
+    /// Connections are grouped by the session id of their handshake, and the
+    /// first session to connect all of its channels is acknowledged and
+    /// returned. Handshakes are read concurrently, so a connection which
+    /// doesn't send one doesn't hold up the others. Connections which don't
+    /// send a valid handshake for `channels` channels and sessions which don't
+    /// connect all of their channels within 10 seconds are closed, as are the
+    /// connections of other sessions once one has been accepted.
+    pub async fn accept(
+        listener: &async_std::net::TcpListener,
+        channels: usize,
+    ) -> Result<Self, io::Error> {
+        check_channels(channels)?;
+        let mut sessions = Sessions::new(channels);
+        let mut handshakes = FuturesUnordered::new();
+        loop {
+            // Accept new connections while reading handshakes
+            let accept = listener.accept();
+            pin_mut!(accept);
+            let next = if handshakes.is_empty() {
+                Either::Left(accept.await)
+            } else {
+                match future::select(accept, handshakes.next()).await {
+                    Either::Left((accepted, _)) => Either::Left(accepted),
+                    Either::Right((handshake, _)) => Either::Right(handshake),
+                }
+            };
+            while sessions.expire(HANDSHAKE_TIMEOUT).is_some() {}
+            let (hello, stream) = match next {
+                Either::Left(accepted) => {
+                    let (mut stream, _) = accepted?;
+                    handshakes.push(async move {
+                        let hello = read_hello_async(&mut stream, HANDSHAKE_TIMEOUT).await;
+                        (hello, stream)
+                    });
+                    continue;
+                }
+                Either::Right(Some((Ok(hello), stream))) => (hello, stream),
+                // Connections with an invalid handshake are closed
+                Either::Right(_) => continue,
+            };
+            let session = hello.session;
+            if let Ok(Some(mut streams)) = sessions.add(hello, stream) {
+                if acknowledge_async(&mut streams, session).await.is_ok() {
+                    return Ok(Self::new(streams));
+                }
+            }
+        }
+    }
+}
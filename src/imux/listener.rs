@@ -0,0 +1,184 @@
+//! Serving many inverse multiplexers from a single TCP listener.
+//!
+//! Connections are accepted continuously and their handshakes are read
+//! concurrently, so a slow or misbehaving peer doesn't hold up the others.
+//! Connections are grouped by the session id of their handshake, and every
+//! session which connects all of its channels is acknowledged and yielded as
+//! an [`IMuxAsync`]. Sessions which don't connect all of their channels within
+//! the timeout are dropped.
+
+use super::{
+    handshake::{acknowledge_async, check_channels, read_hello_async, Hello, Sessions},
+    IMuxAsync, IMuxConfig,
+};
+use async_std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use futures::{
+    future::BoxFuture,
+    io,
+    prelude::*,
+    stream::FuturesUnordered,
+    task::{Context, Poll},
+};
+use futures_timer::Delay;
+use std::{
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The default time a session has to connect all of its channels.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Accept = BoxFuture<'static, Result<(TcpStream, SocketAddr), io::Error>>;
+type Handshake = BoxFuture<'static, Result<(Hello, TcpStream), io::Error>>;
+type Ack = BoxFuture<'static, Result<Vec<TcpStream>, io::Error>>;
+
+/// A TCP listener accepting the channels of many inverse multiplexers
+/// connecting with [`IMuxAsync::connect`] or
+/// [`IMuxSync::connect`](super::IMuxSync::connect).
+///
+/// The listener is a [`Stream`] yielding every session once all of its
+/// channels have connected, so several clients can connect in parallel. An
+/// error is yielded for every connection with an invalid handshake, every
+/// session which times out and every session which can't be acknowledged,
+/// after which the listener keeps accepting connections.
+pub struct IMuxListener {
+    listener: Arc<TcpListener>,
+    config: IMuxConfig,
+    timeout: Duration,
+    accept: Accept,
+    handshakes: FuturesUnordered<Handshake>,
+    sessions: Sessions<TcpStream>,
+    acks: FuturesUnordered<Ack>,
+    // Fires once the oldest pending session times out
+    timer: Option<Delay>,
+}
+
+/// Accepts the next connection on `listener`.
+fn accept(listener: &Arc<TcpListener>) -> Accept {
+    let listener = listener.clone();
+    Box::pin(async move { listener.accept().await })
+}
+
+/// Reads the handshake of `stream`, failing if it doesn't arrive within
+/// `timeout`.
+fn handshake(mut stream: TcpStream, timeout: Duration) -> Handshake {
+    Box::pin(async move { Ok((read_hello_async(&mut stream, timeout).await?, stream)) })
+}
+
+/// Acknowledges the channels `streams` of `session`.
+fn ack(mut streams: Vec<TcpStream>, session: u64) -> Ack {
+    Box::pin(async move {
+        acknowledge_async(&mut streams, session).await?;
+        Ok(streams)
+    })
+}
+
+impl IMuxListener {
+    /// Constructs a new `IMuxListener` accepting inverse multiplexers of
+    /// `channels` channels on `listener`.
+    ///
+    /// Panics if `channels` is zero.
+    pub fn new(listener: TcpListener, channels: usize) -> Self {
+        check_channels(channels).unwrap();
+        let listener = Arc::new(listener);
+        Self {
+            accept: accept(&listener),
+            listener,
+            config: IMuxConfig::default(),
+            timeout: DEFAULT_TIMEOUT,
+            handshakes: FuturesUnordered::new(),
+            sessions: Sessions::new(channels),
+            acks: FuturesUnordered::new(),
+            timer: None,
+        }
+    }
+
+    /// Binds a new `IMuxListener` accepting inverse multiplexers of
+    /// `channels` channels to `addr`.
+    pub async fn bind<A: ToSocketAddrs>(addr: A, channels: usize) -> Result<Self, io::Error> {
+        check_channels(channels)?;
+        Ok(Self::new(TcpListener::bind(addr).await?, channels))
+    }
+
+    /// Sets the configuration of the inverse multiplexers yielded.
+    pub fn with_config(mut self, config: IMuxConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets the time a session has to connect all of its channels, starting
+    /// from the handshake of its first channel. Connections also have to send
+    /// their handshake within this time. Defaults to 10 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the local address the listener is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.listener.local_addr()
+    }
+
+    /// Returns a reference to the underlying listener.
+    pub fn get_ref(&self) -> &TcpListener {
+        &self.listener
+    }
+}
+
+impl Stream for IMuxListener {
+    type Item = Result<IMuxAsync<TcpStream>, io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        // Start reading the handshakes of new connections
+        while let Poll::Ready(result) = this.accept.as_mut().poll(ctx) {
+            this.accept = accept(&this.listener);
+            match result {
+                Ok((stream, _)) => this.handshakes.push(handshake(stream, this.timeout)),
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+        // Group connections into sessions by their handshakes
+        while let Poll::Ready(Some(result)) = this.handshakes.poll_next_unpin(ctx) {
+            let sessions = &mut this.sessions;
+            let result = result.and_then(|(hello, stream)| {
+                let session = hello.session;
+                Ok(sessions
+                    .add(hello, stream)?
+                    .map(|streams| (streams, session)))
+            });
+            match result {
+                Ok(Some((streams, session))) => this.acks.push(ack(streams, session)),
+                Ok(None) => {}
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+        // Yield the sessions which have been acknowledged
+        if let Poll::Ready(Some(result)) = this.acks.poll_next_unpin(ctx) {
+            let imux = result.map(|streams| IMuxAsync::with_config(streams, this.config));
+            return Poll::Ready(Some(imux));
+        }
+        // Drop the sessions which timed out
+        loop {
+            if this.timer.is_none() {
+                match this.sessions.deadline(this.timeout) {
+                    Some(deadline) => {
+                        let delay = deadline.saturating_duration_since(Instant::now());
+                        this.timer = Some(Delay::new(delay));
+                    }
+                    None => return Poll::Pending,
+                }
+            }
+            match this.timer.as_mut().unwrap().poll_unpin(ctx) {
+                Poll::Ready(()) => {
+                    this.timer = None;
+                    if let Some(e) = this.sessions.expire(this.timeout) {
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Reassembling a message from chunks received on all channels at once.
+//!
+//! A message received into a vector is only allocated as its bytes arrive, so
+//! that a peer can't make us allocate much more memory than it has actually
+//! sent. Chunks received ahead of the allocated part of the message are held
+//! back until enough of the message has arrived.
+
+use super::grow;
+use futures::io;
+use std::collections::BTreeMap;
+
+/// A buffer a message can be received into.
+pub(super) trait MessageBuf: Send {
+    /// Returns the buffer once it covers the message up to `end`, growing it
+    /// as far as `received` bytes of the message of `total` bytes allow, or
+    /// `None` if the buffer can't grow that far yet.
+    fn cover(&mut self, end: usize, received: usize, total: usize) -> Option<&mut [u8]>;
+
+    /// Returns the number of bytes of the message covered by the buffer.
+    fn covered(&self) -> usize;
+}
+
+impl MessageBuf for Vec<u8> {
+    fn cover(&mut self, end: usize, received: usize, total: usize) -> Option<&mut [u8]> {
+        if self.len() < end {
+            if end > grow(received, total, self.capacity()) {
+                return None;
+            }
+            self.resize(end, 0);
+        }
+        Some(self)
+    }
+
+    fn covered(&self) -> usize {
+        self.len()
+    }
+}
+
+/// A slice already covers the whole message.
+impl MessageBuf for [u8] {
+    fn cover(&mut self, _: usize, _: usize, _: usize) -> Option<&mut [u8]> {
+        Some(self)
+    }
+
+    fn covered(&self) -> usize {
+        self.len()
+    }
+}
+
+/// A message of a known length being received into a buffer.
+pub(super) struct Message<'a, B: ?Sized> {
+    buf: &'a mut B,
+    total: usize,
+    received: usize,
+    // The chunks which don't fit into the buffer yet by their offsets
+    pending: BTreeMap<usize, Vec<u8>>,
+}
+
+impl<'a, B: MessageBuf + ?Sized> Message<'a, B> {
+    /// Starts receiving a message of `total` bytes into `buf`, which is either
+    /// an empty vector or a slice of length `total`.
+    pub(super) fn new(buf: &'a mut B, total: usize) -> Self {
+        Self {
+            buf,
+            total,
+            received: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Stores `chunk` at `offset` in the message, which has been checked to
+    /// lie within the message.
+    pub(super) fn store(&mut self, offset: usize, chunk: &[u8]) {
+        self.received += chunk.len();
+        match self
+            .buf
+            .cover(offset + chunk.len(), self.received, self.total)
+        {
+            Some(buf) => buf[offset..offset + chunk.len()].copy_from_slice(chunk),
+            None => {
+                self.pending.insert(offset, chunk.to_vec());
+            }
+        }
+        // The buffer may grow far enough for chunks held back before
+        while let Some(entry) = self.pending.first_entry() {
+            let (offset, len) = (*entry.key(), entry.get().len());
+            match self.buf.cover(offset + len, self.received, self.total) {
+                Some(buf) => buf[offset..offset + len].copy_from_slice(&entry.remove()),
+                None => break,
+            }
+        }
+    }
+
+    /// Checks that the chunks received add up to the whole message.
+    pub(super) fn finish(self) -> Result<(), io::Error> {
+        if self.received != self.total || self.buf.covered() != self.total {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Received {} of {} message bytes", self.received, self.total),
+            ));
+        }
+        Ok(())
+    }
+}
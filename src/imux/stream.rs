@@ -0,0 +1,302 @@
+use super::IMuxAsync;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures::{io, ready, AsyncRead, AsyncWrite};
+
+/// The maximum number of payload bytes in a single frame.
+pub(super) const FRAME_SIZE: usize = 65536;
+/// The length of a frame header: an 8 byte sequence number followed by a
+/// 4 byte payload length, both little-endian.
+pub(super) const HEADER_LEN: usize = 12;
+
+/// Encodes the header of frame `seq` holding `len` payload bytes.
+pub(super) fn encode_header(seq: u64, len: usize) -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    header[..8].copy_from_slice(&seq.to_le_bytes());
+    header[8..].copy_from_slice(&(len as u32).to_le_bytes());
+    header
+}
+
+/// Decodes a frame header into the sequence number and payload length.
+pub(super) fn decode_header(header: &[u8; HEADER_LEN]) -> Result<(u64, usize), io::Error> {
+    let mut seq = [0u8; 8];
+    seq.copy_from_slice(&header[..8]);
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&header[8..]);
+    let len = u32::from_le_bytes(len) as usize;
+    if len == 0 || len > FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid frame length {}", len),
+        ));
+    }
+    Ok((u64::from_le_bytes(seq), len))
+}
+
+/// Returns the error for a frame received out of order.
+pub(super) fn out_of_order(expected: u64, seq: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Expected frame {} but received frame {}", expected, seq),
+    )
+}
+
+/// A streaming interface to an [`IMuxAsync<I>`] inverse multiplexer.
+///
+/// `IMuxStream` implements the [`AsyncRead`]/[`AsyncWrite`] traits, so it can
+/// be used in place of a single network stream. Bytes written are collected
+/// into frames of up to 64KiB, which are tagged with a sequence number and
+/// sent across the channels in turn. The receiving side reads from all
+/// channels concurrently and reassembles the frames in order.
+///
+/// A partially filled frame is only sent once it is full or the stream is
+/// flushed, so [`AsyncWriteExt::flush`] must be called before waiting for a
+/// response. Both endpoints of a connection must use an `IMuxStream` over the
+/// same number of channels.
+///
+/// [`AsyncWriteExt::flush`]: `futures::io::AsyncWriteExt::flush`
+pub struct IMuxStream<I> {
+    imux: IMuxAsync<I>,
+    incoming: Vec<Incoming>,
+    outgoing: Vec<Outgoing>,
+    // The frame currently being read from along with the read position
+    current: Vec<u8>,
+    current_pos: usize,
+    read_seq: u64,
+    // The payload of the next frame to be sent
+    pending: Vec<u8>,
+    write_seq: u64,
+}
+
+/// The frame currently being received on a channel.
+#[derive(Default)]
+struct Incoming {
+    header: [u8; HEADER_LEN],
+    header_filled: usize,
+    payload: Vec<u8>,
+    payload_filled: usize,
+    ready: bool,
+    eof: bool,
+}
+
+/// The frame currently being sent on a channel.
+#[derive(Default)]
+struct Outgoing {
+    frame: Vec<u8>,
+    pos: usize,
+}
+
+impl<I> IMuxAsync<I> {
+    /// Converts the inverse multiplexer into an [`IMuxStream`] implementing
+    /// the [`AsyncRead`]/[`AsyncWrite`] traits.
+    pub fn into_stream(self) -> IMuxStream<I> {
+        let channels = self.channels.len();
+        IMuxStream {
+            imux: self,
+            incoming: (0..channels).map(|_| Incoming::default()).collect(),
+            outgoing: (0..channels).map(|_| Outgoing::default()).collect(),
+            current: Vec::new(),
+            current_pos: 0,
+            read_seq: 0,
+            pending: Vec::with_capacity(FRAME_SIZE),
+            write_seq: 0,
+        }
+    }
+}
+
+impl<I> IMuxStream<I> {
+    /// Returns a reference to the wrapped inverse multiplexer.
+    pub fn inner(&self) -> &IMuxAsync<I> {
+        &self.imux
+    }
+
+    /// Returns a mutable reference to the wrapped inverse multiplexer.
+    pub fn inner_mut(&mut self) -> &mut IMuxAsync<I> {
+        &mut self.imux
+    }
+
+    /// Consumes the stream and returns the wrapped inverse multiplexer. Any
+    /// buffered data which hasn't been read or sent yet is discarded.
+    pub fn into_inner(self) -> IMuxAsync<I> {
+        self.imux
+    }
+
+    /// Moves the pending payload into the outgoing frame of its channel.
+    /// Returns `false` if that channel is still busy sending an earlier frame.
+    fn seal(&mut self) -> bool {
+        let channel = (self.write_seq % self.outgoing.len() as u64) as usize;
+        let outgoing = &mut self.outgoing[channel];
+        if outgoing.pos < outgoing.frame.len() {
+            return false;
+        }
+        outgoing.frame.clear();
+        outgoing
+            .frame
+            .extend(&encode_header(self.write_seq, self.pending.len()));
+        outgoing.frame.append(&mut self.pending);
+        outgoing.pos = 0;
+        self.write_seq += 1;
+        true
+    }
+}
+
+impl<I: AsyncRead + Unpin> IMuxStream<I> {
+    /// Polls every channel without a complete frame for more data.
+    fn poll_receive(&mut self, ctx: &mut Context<'_>) -> Result<(), io::Error> {
+        for (channel, incoming) in self.imux.channels.iter_mut().zip(&mut self.incoming) {
+            while !incoming.ready && !incoming.eof {
+                let buf = if incoming.header_filled < HEADER_LEN {
+                    &mut incoming.header[incoming.header_filled..]
+                } else {
+                    &mut incoming.payload[incoming.payload_filled..]
+                };
+                let bytes = match Pin::new(&mut *channel).poll_read(ctx, buf)? {
+                    Poll::Ready(bytes) => bytes,
+                    Poll::Pending => break,
+                };
+                if bytes == 0 {
+                    // A channel may only be closed between frames
+                    if incoming.header_filled > 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    incoming.eof = true;
+                } else if incoming.header_filled < HEADER_LEN {
+                    incoming.header_filled += bytes;
+                    if incoming.header_filled == HEADER_LEN {
+                        let (_, len) = decode_header(&incoming.header)?;
+                        incoming.payload = vec![0u8; len];
+                        incoming.payload_filled = 0;
+                    }
+                } else {
+                    incoming.payload_filled += bytes;
+                    incoming.ready = incoming.payload_filled == incoming.payload.len();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<I: AsyncWrite + Unpin> IMuxStream<I> {
+    /// Sends as much of the outgoing frames as possible. Returns `true` once
+    /// all outgoing frames have been sent.
+    fn poll_send(&mut self, ctx: &mut Context<'_>) -> Result<bool, io::Error> {
+        let mut done = true;
+        for (channel, outgoing) in self.imux.channels.iter_mut().zip(&mut self.outgoing) {
+            while outgoing.pos < outgoing.frame.len() {
+                match Pin::new(&mut *channel).poll_write(ctx, &outgoing.frame[outgoing.pos..])? {
+                    Poll::Ready(0) => return Err(io::ErrorKind::WriteZero.into()),
+                    Poll::Ready(bytes) => outgoing.pos += bytes,
+                    Poll::Pending => {
+                        done = false;
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(done)
+    }
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for IMuxStream<I> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        loop {
+            if this.current_pos < this.current.len() {
+                let bytes = buf.len().min(this.current.len() - this.current_pos);
+                buf[..bytes]
+                    .copy_from_slice(&this.current[this.current_pos..this.current_pos + bytes]);
+                this.current_pos += bytes;
+                return Poll::Ready(Ok(bytes));
+            }
+
+            this.poll_receive(ctx)?;
+            let channel = (this.read_seq % this.incoming.len() as u64) as usize;
+            let incoming = &mut this.incoming[channel];
+            if !incoming.ready {
+                // The stream ends once the channel holding the next frame is
+                // closed
+                return if incoming.eof {
+                    Poll::Ready(Ok(0))
+                } else {
+                    Poll::Pending
+                };
+            }
+            let (seq, _) = decode_header(&incoming.header)?;
+            if seq != this.read_seq {
+                return Poll::Ready(Err(out_of_order(this.read_seq, seq)));
+            }
+            this.current = std::mem::take(&mut incoming.payload);
+            this.current_pos = 0;
+            this.read_seq += 1;
+            incoming.header_filled = 0;
+            incoming.ready = false;
+        }
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for IMuxStream<I> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        this.poll_send(ctx)?;
+        if this.pending.len() == FRAME_SIZE && !this.seal() {
+            return Poll::Pending;
+        }
+        let bytes = buf.len().min(FRAME_SIZE - this.pending.len());
+        this.pending.extend_from_slice(&buf[..bytes]);
+        if this.pending.len() == FRAME_SIZE && this.seal() {
+            this.poll_send(ctx)?;
+        }
+        Poll::Ready(Ok(bytes))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = self.get_mut();
+        let mut done = this.poll_send(ctx)?;
+        if !this.pending.is_empty() {
+            if !this.seal() {
+                return Poll::Pending;
+            }
+            done = this.poll_send(ctx)?;
+        }
+        if !done {
+            return Poll::Pending;
+        }
+        let mut flushed = true;
+        for channel in &mut this.imux.channels {
+            flushed &= Pin::new(channel).poll_flush(ctx)?.is_ready();
+        }
+        if flushed {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        ready!(self.as_mut().poll_flush(ctx))?;
+        let mut closed = true;
+        for channel in &mut self.get_mut().imux.channels {
+            closed &= Pin::new(channel).poll_close(ctx)?.is_ready();
+        }
+        if closed {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use super::{
+    stream::{decode_header, encode_header, out_of_order, FRAME_SIZE, HEADER_LEN},
+    IMuxConfig, IMuxSync,
+};
+use futures::io;
+use std::{
+    io::{Read, Write},
+    mem,
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+/// The maximum number of frames queued for each channel.
+const QUEUE_LEN: usize = 4;
+
+/// The sequence number and payload of a received frame.
+type Frame = (u64, Vec<u8>);
+
+/// A streaming reader over an [`IMuxSync<I>`] inverse multiplexer.
+///
+/// `IMuxReader` implements the [`Read`] trait by reading the frames sent by an
+/// [`IMuxWriter`] or an [`IMuxStream`] from all channels concurrently, using a
+/// background thread per channel, and reassembling them in order. Each thread
+/// reads ahead by a bounded number of frames.
+///
+/// The background threads exit once their channel is closed by the peer or
+/// fails. A read error on any channel is returned by the read which reaches
+/// the frame it occurred at.
+///
+/// [`IMuxStream`]: `super::IMuxStream`
+pub struct IMuxReader {
+    receivers: Vec<Receiver<Result<Frame, io::Error>>>,
+    // The frame currently being read from along with the read position
+    current: Vec<u8>,
+    current_pos: usize,
+    read_seq: u64,
+}
+
+/// A streaming writer over an [`IMuxSync<I>`] inverse multiplexer.
+///
+/// `IMuxWriter` implements the [`Write`] trait by collecting the bytes written
+/// into frames of up to 64KiB, which are tagged with a sequence number and
+/// sent across the channels in turn by a background thread per channel. Each
+/// thread queues a bounded number of frames, after which writes block.
+///
+/// A partially filled frame is only sent once it is full or the writer is
+/// flushed, so [`Write::flush`] must be called before waiting for a response.
+/// Since frames are sent in the background, an error on any channel is
+/// returned by the next write or flush after it occurred, and all further
+/// frames are discarded. Dropping the writer flushes it and waits for the
+/// background threads to finish, ignoring any errors.
+pub struct IMuxWriter<I> {
+    workers: Vec<Worker<I>>,
+    acks: Receiver<()>,
+    error: Arc<Mutex<Option<io::Error>>>,
+    config: IMuxConfig,
+    // The message ids of the inverse multiplexer, restored by `into_inner`
+    read_id: u64,
+    write_id: u64,
+    // The payload of the next frame to be sent
+    pending: Vec<u8>,
+    write_seq: u64,
+}
+
+/// A command for the background thread of an [`IMuxWriter`] channel.
+enum Command {
+    Frame(Vec<u8>),
+    Flush,
+}
+
+/// The background thread of an [`IMuxWriter`] channel, which returns the
+/// channel unless it failed.
+struct Worker<I> {
+    sender: SyncSender<Command>,
+    handle: JoinHandle<Option<I>>,
+}
+
+impl<I: Read + Send + 'static> IMuxSync<I> {
+    /// Converts the inverse multiplexer into an [`IMuxReader`] implementing
+    /// the [`Read`] trait.
+    pub fn into_reader(self) -> IMuxReader {
+        let receivers = self
+            .channels
+            .into_iter()
+            .map(|channel| {
+                let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+                thread::spawn(move || IMuxReader::read_loop(channel, sender));
+                receiver
+            })
+            .collect();
+        IMuxReader {
+            receivers,
+            current: Vec::new(),
+            current_pos: 0,
+            read_seq: 0,
+        }
+    }
+}
+
+impl<I: Write + Send + 'static> IMuxSync<I> {
+    /// Converts the inverse multiplexer into an [`IMuxWriter`] implementing
+    /// the [`Write`] trait.
+    pub fn into_writer(self) -> IMuxWriter<I> {
+        let (ack_sender, acks) = mpsc::channel();
+        let error = Arc::new(Mutex::new(None));
+        let workers = self
+            .channels
+            .into_iter()
+            .map(|channel| {
+                let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+                let (acks, error) = (ack_sender.clone(), error.clone());
+                let handle =
+                    thread::spawn(move || IMuxWriter::write_loop(channel, receiver, acks, error));
+                Worker { sender, handle }
+            })
+            .collect();
+        IMuxWriter {
+            workers,
+            acks,
+            error,
+            config: self.config,
+            read_id: self.read_id,
+            write_id: self.write_id,
+            pending: Vec::with_capacity(FRAME_SIZE),
+            write_seq: 0,
+        }
+    }
+}
+
+impl IMuxReader {
+    /// The loop executed by the background thread of each channel
+    fn read_loop<I: Read>(mut channel: I, sender: SyncSender<Result<Frame, io::Error>>) {
+        // Stop once the channel is closed or fails, or the reader is dropped
+        while let Some(frame) = Self::read_frame(&mut channel).transpose() {
+            let failed = frame.is_err();
+            if sender.send(frame).is_err() || failed {
+                return;
+            }
+        }
+    }
+
+    /// Reads the next frame from `channel`, or `None` if the channel has been
+    /// closed.
+    fn read_frame<I: Read>(channel: &mut I) -> Result<Option<Frame>, io::Error> {
+        let mut header = [0u8; HEADER_LEN];
+        // A channel may only be closed between frames
+        let mut filled = 0;
+        while filled < HEADER_LEN {
+            match channel.read(&mut header[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(bytes) => filled += bytes,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let (seq, len) = decode_header(&header)?;
+        let mut payload = vec![0u8; len];
+        channel.read_exact(&mut payload)?;
+        Ok(Some((seq, payload)))
+    }
+}
+
+impl Read for IMuxReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current_pos == self.current.len() {
+            let channel = (self.read_seq % self.receivers.len() as u64) as usize;
+            // The stream ends once the channel holding the next frame is closed
+            let (seq, payload) = match self.receivers[channel].recv() {
+                Ok(frame) => frame?,
+                Err(_) => return Ok(0),
+            };
+            if seq != self.read_seq {
+                return Err(out_of_order(self.read_seq, seq));
+            }
+            self.current = payload;
+            self.current_pos = 0;
+            self.read_seq += 1;
+        }
+        let bytes = buf.len().min(self.current.len() - self.current_pos);
+        buf[..bytes].copy_from_slice(&self.current[self.current_pos..self.current_pos + bytes]);
+        self.current_pos += bytes;
+        Ok(bytes)
+    }
+}
+
+impl<I> IMuxWriter<I> {
+    /// The loop executed by the background thread of each channel
+    fn write_loop(
+        mut channel: I,
+        receiver: Receiver<Command>,
+        acks: mpsc::Sender<()>,
+        error: Arc<Mutex<Option<io::Error>>>,
+    ) -> Option<I>
+    where
+        I: Write,
+    {
+        // Frames after an error are discarded since the stream is corrupted,
+        // but flushes are still acknowledged so the writer doesn't block
+        let mut failed = false;
+        let record = |ret: Result<(), io::Error>| match ret {
+            Ok(()) => false,
+            Err(e) => {
+                error.lock().unwrap().get_or_insert(e);
+                true
+            }
+        };
+        // Stop once the writer has been dropped or converted back
+        while let Ok(command) = receiver.recv() {
+            match command {
+                Command::Frame(frame) if !failed => failed = record(channel.write_all(&frame)),
+                Command::Frame(_) => {}
+                Command::Flush => {
+                    if !failed {
+                        failed = record(channel.flush());
+                    }
+                    let _ = acks.send(());
+                }
+            }
+        }
+        if !failed {
+            failed = record(channel.flush());
+        }
+        Some(channel).filter(|_| !failed)
+    }
+
+    /// Returns the first error encountered by any of the background threads.
+    fn check(&self) -> Result<(), io::Error> {
+        match &*self.error.lock().unwrap() {
+            Some(e) => Err(io::Error::new(e.kind(), e.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Queues `command` for the background thread of `channel`.
+    fn send(&self, channel: usize, command: Command) -> Result<(), io::Error> {
+        self.workers[channel].sender.send(command).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                format!("The writer thread of channel {} has stopped", channel),
+            )
+        })
+    }
+
+    /// Sends the pending payload as a frame.
+    fn send_pending(&mut self) -> Result<(), io::Error> {
+        let channel = (self.write_seq % self.workers.len() as u64) as usize;
+        let mut frame = Vec::with_capacity(HEADER_LEN + self.pending.len());
+        frame.extend(&encode_header(self.write_seq, self.pending.len()));
+        frame.append(&mut self.pending);
+        self.send(channel, Command::Frame(frame))?;
+        self.write_seq += 1;
+        Ok(())
+    }
+
+    /// Waits for the background threads to finish and returns the channels,
+    /// or `None` if any of them failed or panicked.
+    fn join(&mut self) -> Option<Vec<I>> {
+        mem::take(&mut self.workers)
+            .into_iter()
+            .map(|Worker { sender, handle }| {
+                // Closing the queue makes the thread flush its channel and exit
+                drop(sender);
+                handle.join().ok().flatten()
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Flushes the writer and returns the wrapped inverse multiplexer, or the
+    /// first error encountered by any of the background threads.
+    pub fn into_inner(mut self) -> Result<IMuxSync<I>, io::Error>
+    where
+        I: Write,
+    {
+        if !self.pending.is_empty() {
+            self.send_pending()?;
+        }
+        match self.join() {
+            Some(channels) => Ok(IMuxSync {
+                channels,
+                config: self.config,
+                read_id: self.read_id,
+                write_id: self.write_id,
+                scratch: Vec::new(),
+            }),
+            // A thread which panicked didn't record an error
+            None => Err(self
+                .check()
+                .err()
+                .unwrap_or_else(|| io::Error::other("A writer thread panicked"))),
+        }
+    }
+}
+
+impl<I: Write> Write for IMuxWriter<I> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        self.check()?;
+        let bytes = buf.len().min(FRAME_SIZE - self.pending.len());
+        self.pending.extend_from_slice(&buf[..bytes]);
+        if self.pending.len() == FRAME_SIZE {
+            self.send_pending()?;
+        }
+        Ok(bytes)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.check()?;
+        if !self.pending.is_empty() {
+            self.send_pending()?;
+        }
+        // Wait until every channel has written and flushed its queued frames
+        for channel in 0..self.workers.len() {
+            self.send(channel, Command::Flush)?;
+        }
+        for _ in 0..self.workers.len() {
+            self.acks
+                .recv()
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+        self.check()
+    }
+}
+
+impl<I> Drop for IMuxWriter<I> {
+    fn drop(&mut self) {
+        if !self.pending.is_empty() {
+            let _ = self.send_pending();
+        }
+        self.join();
+    }
+}
@@ -0,0 +1,197 @@
+//! Sending and receiving typed messages.
+//!
+//! With the `serde` feature enabled, values implementing [`Serialize`] are
+//! sent with `send` and received with `recv`, encoded with [`bincode`]. With
+//! the `ark-serialize` feature enabled, values implementing
+//! [`CanonicalSerialize`] are sent with `send_canonical` and received with
+//! `recv_canonical` in the given [`Compression`] mode.
+//!
+//! Every value is sent as a single message, so both sides have to agree on the
+//! type and, for canonical serialization, the compression mode. A message
+//! which doesn't decode into exactly one value fails with
+//! [`InvalidData`](io::ErrorKind::InvalidData).
+//!
+//! [`Serialize`]: serde::Serialize
+//! [`CanonicalSerialize`]: ark_serialize::CanonicalSerialize
+
+use super::{wire::invalid, IMuxAsync, IMuxSync};
+#[cfg(feature = "ark-serialize")]
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use futures::{io, AsyncRead, AsyncWrite};
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+};
+
+/// How values are encoded by canonical serialization.
+#[cfg(feature = "ark-serialize")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// Elliptic curve points are encoded by their x-coordinate only.
+    Compressed,
+    /// Elliptic curve points are encoded by both coordinates, which is larger
+    /// but faster to decode.
+    Uncompressed,
+}
+
+/// Returns an error for a value which couldn't be serialized.
+fn serialize_error(e: impl Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Failed to serialize the message: {}", e),
+    )
+}
+
+/// Returns an error for a received message of `len` bytes which couldn't be
+/// deserialized.
+fn deserialize_error(len: usize, e: impl Display) -> io::Error {
+    invalid(format!(
+        "Failed to deserialize a message of {} bytes: {}",
+        len, e
+    ))
+}
+
+/// Checks that deserializing a message of `len` bytes left no bytes `rest`.
+fn check_consumed(rest: &[u8], len: usize) -> Result<(), io::Error> {
+    if !rest.is_empty() {
+        return Err(invalid(format!(
+            "Only {} of the {} message bytes were deserialized",
+            len - rest.len(),
+            len
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, io::Error> {
+    bincode::serialize(value).map_err(serialize_error)
+}
+
+#[cfg(feature = "serde")]
+fn decode<T: DeserializeOwned>(message: &[u8]) -> Result<T, io::Error> {
+    let mut rest = message;
+    let value =
+        bincode::deserialize_from(&mut rest).map_err(|e| deserialize_error(message.len(), e))?;
+    check_consumed(rest, message.len())?;
+    Ok(value)
+}
+
+#[cfg(feature = "ark-serialize")]
+fn encode_canonical<T: CanonicalSerialize>(
+    value: &T,
+    compression: Compression,
+) -> Result<Vec<u8>, io::Error> {
+    let result = match compression {
+        Compression::Compressed => {
+            let mut buf = Vec::with_capacity(value.serialized_size());
+            value.serialize(&mut buf).map(|_| buf)
+        }
+        Compression::Uncompressed => {
+            let mut buf = Vec::with_capacity(value.uncompressed_size());
+            value.serialize_uncompressed(&mut buf).map(|_| buf)
+        }
+    };
+    result.map_err(serialize_error)
+}
+
+#[cfg(feature = "ark-serialize")]
+fn decode_canonical<T: CanonicalDeserialize>(
+    message: &[u8],
+    compression: Compression,
+) -> Result<T, io::Error> {
+    let mut rest = message;
+    let value = match compression {
+        Compression::Compressed => T::deserialize(&mut rest),
+        Compression::Uncompressed => T::deserialize_uncompressed(&mut rest),
+    }
+    .map_err(|e| deserialize_error(message.len(), e))?;
+    check_consumed(rest, message.len())?;
+    Ok(value)
+}
+
+impl<I: Read + Send> IMuxSync<I> {
+    /// Receives a message and deserializes it into a value of type `T`
+    /// encoded with [`bincode`].
+    #[cfg(feature = "serde")]
+    pub fn recv<T: DeserializeOwned>(&mut self) -> Result<T, io::Error> {
+        decode(&self.read()?)
+    }
+
+    /// Receives a message and deserializes it into a value of type `T`
+    /// encoded with canonical serialization in the given `compression` mode.
+    #[cfg(feature = "ark-serialize")]
+    pub fn recv_canonical<T: CanonicalDeserialize>(
+        &mut self,
+        compression: Compression,
+    ) -> Result<T, io::Error> {
+        decode_canonical(&self.read()?, compression)
+    }
+}
+
+impl<I: Write + Send> IMuxSync<I> {
+    /// Serializes `value` with [`bincode`] and sends it as a single message.
+    ///
+    /// As with `write`, the channels have to be flushed afterwards.
+    #[cfg(feature = "serde")]
+    pub fn send<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), io::Error> {
+        self.write(&encode(value)?)
+    }
+
+    /// Serializes `value` with canonical serialization in the given
+    /// `compression` mode and sends it as a single message.
+    ///
+    /// As with `write`, the channels have to be flushed afterwards.
+    #[cfg(feature = "ark-serialize")]
+    pub fn send_canonical<T: CanonicalSerialize>(
+        &mut self,
+        value: &T,
+        compression: Compression,
+    ) -> Result<(), io::Error> {
+        self.write(&encode_canonical(value, compression)?)
+    }
+}
+
+impl<I: AsyncRead + Unpin> IMuxAsync<I> {
+    /// Receives a message and deserializes it into a value of type `T`
+    /// encoded with [`bincode`].
+    #[cfg(feature = "serde")]
+    pub async fn recv<T: DeserializeOwned>(&mut self) -> Result<T, io::Error> {
+        decode(&self.read().await?)
+    }
+
+    /// Receives a message and deserializes it into a value of type `T`
+    /// encoded with canonical serialization in the given `compression` mode.
+    #[cfg(feature = "ark-serialize")]
+    pub async fn recv_canonical<T: CanonicalDeserialize>(
+        &mut self,
+        compression: Compression,
+    ) -> Result<T, io::Error> {
+        decode_canonical(&self.read().await?, compression)
+    }
+}
+
+impl<I: AsyncWrite + Unpin> IMuxAsync<I> {
+    /// Serializes `value` with [`bincode`] and sends it as a single message.
+    ///
+    /// As with `write`, the channels have to be flushed afterwards.
+    #[cfg(feature = "serde")]
+    pub async fn send<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), io::Error> {
+        self.write(&encode(value)?).await
+    }
+
+    /// Serializes `value` with canonical serialization in the given
+    /// `compression` mode and sends it as a single message.
+    ///
+    /// As with `write`, the channels have to be flushed afterwards.
+    #[cfg(feature = "ark-serialize")]
+    pub async fn send_canonical<T: CanonicalSerialize>(
+        &mut self,
+        value: &T,
+        compression: Compression,
+    ) -> Result<(), io::Error> {
+        self.write(&encode_canonical(value, compression)?).await
+    }
+}
@@ -0,0 +1,192 @@
+//! The header preceding every message sent by an inverse multiplexer, and the
+//! checksums optionally following every chunk.
+//!
+//! The header is sent on the first channel and consists of:
+//!
+//! | Bytes  | Field                                                       |
+//! |--------|-------------------------------------------------------------|
+//! | 0..4   | The magic bytes `IMUX`                                      |
+//! | 4      | The version of the wire format                              |
+//! | 5      | Flags: bit 0 for checksums, bit 1 for the adaptive schedule |
+//! | 6..8   | Reserved                                                    |
+//! | 8..12  | The number of channels                                      |
+//! | 12..20 | The chunk size                                              |
+//! | 20..28 | The message id, counting the messages sent                  |
+//! | 28..36 | The message length                                          |
+//!
+//! All integers are little-endian. With checksums enabled, every chunk is
+//! followed by the CRC32 of its contents on the same channel.
+
+use super::IMuxConfig;
+use futures::io;
+use std::convert::TryInto;
+
+/// The magic bytes identifying a message header.
+const MAGIC: [u8; 4] = *b"IMUX";
+/// The version of the wire format.
+const VERSION: u8 = 1;
+/// The header flag indicating that chunks are followed by checksums.
+const FLAG_CHECKSUMS: u8 = 1;
+/// The header flag indicating that the adaptive schedule is used.
+const FLAG_ADAPTIVE: u8 = 1 << 1;
+
+/// The length of a message header.
+pub(super) const HEADER_LEN: usize = 36;
+/// The length of a chunk checksum.
+pub(super) const CHECKSUM_LEN: usize = 4;
+
+/// The header of a message.
+#[derive(Debug)]
+pub(super) struct Header {
+    flags: u8,
+    channels: u32,
+    chunk_size: u64,
+    id: u64,
+    len: u64,
+}
+
+/// Returns an error for invalid data received from the peer.
+pub(super) fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Describes whether a feature is enabled in error messages.
+fn enabled(flag: bool) -> &'static str {
+    if flag {
+        "enabled"
+    } else {
+        "disabled"
+    }
+}
+
+impl Header {
+    /// Constructs the header of message `id` of `len` bytes, sent across
+    /// `channels` channels according to `config`.
+    pub(super) fn new(config: &IMuxConfig, channels: usize, id: u64, len: usize) -> Self {
+        let mut flags = 0;
+        if config.checksums_enabled() {
+            flags |= FLAG_CHECKSUMS;
+        }
+        let chunk_size = match config.adaptive_chunk_size() {
+            Some(chunk_size) => {
+                flags |= FLAG_ADAPTIVE;
+                chunk_size
+            }
+            None => config.chunk_size(len, channels),
+        };
+        Self {
+            flags,
+            channels: channels as u32,
+            chunk_size: chunk_size as u64,
+            id,
+            len: len as u64,
+        }
+    }
+
+    pub(super) fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut header = [0u8; HEADER_LEN];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4] = VERSION;
+        header[5] = self.flags;
+        header[8..12].copy_from_slice(&self.channels.to_le_bytes());
+        header[12..20].copy_from_slice(&self.chunk_size.to_le_bytes());
+        header[20..28].copy_from_slice(&self.id.to_le_bytes());
+        header[28..36].copy_from_slice(&self.len.to_le_bytes());
+        header
+    }
+
+    /// Decodes a header, checking its magic bytes and version.
+    pub(super) fn decode(header: &[u8; HEADER_LEN]) -> Result<Self, io::Error> {
+        if header[..4] != MAGIC {
+            return Err(invalid(format!(
+                "Invalid message header {:?}: the peer isn't an inverse multiplexer or \
+                 its channels are connected in a different order",
+                &header[..4]
+            )));
+        }
+        if header[4] != VERSION {
+            return Err(invalid(format!(
+                "The peer uses version {} of the wire format but version {} is supported",
+                header[4], VERSION
+            )));
+        }
+        Ok(Self {
+            flags: header[5],
+            channels: u32::from_le_bytes(header[8..12].try_into().unwrap()),
+            chunk_size: u64::from_le_bytes(header[12..20].try_into().unwrap()),
+            id: u64::from_le_bytes(header[20..28].try_into().unwrap()),
+            len: u64::from_le_bytes(header[28..36].try_into().unwrap()),
+        })
+    }
+
+    /// Checks that the header of message `id` agrees with `config` and the
+    /// number of `channels`, and returns the length of the message.
+    pub(super) fn check(
+        &self,
+        config: &IMuxConfig,
+        channels: usize,
+        id: u64,
+    ) -> Result<usize, io::Error> {
+        let checksums = self.flags & FLAG_CHECKSUMS != 0;
+        if checksums != config.checksums_enabled() {
+            return Err(invalid(format!(
+                "Checksums are {} by the peer but {} locally",
+                enabled(checksums),
+                enabled(config.checksums_enabled())
+            )));
+        }
+        let adaptive = self.flags & FLAG_ADAPTIVE != 0;
+        if adaptive != config.adaptive_chunk_size().is_some() {
+            let schedule = |adaptive| if adaptive { "adaptive" } else { "static" };
+            return Err(invalid(format!(
+                "The peer uses the {} schedule but the {} schedule is configured",
+                schedule(adaptive),
+                schedule(!adaptive)
+            )));
+        }
+        if self.channels as usize != channels {
+            return Err(invalid(format!(
+                "The peer sends across {} channels but {} channels are connected",
+                self.channels, channels
+            )));
+        }
+        let len = config.check_message_size(self.len)?;
+        let expected = Self::new(config, channels, id, len);
+        if self.chunk_size != expected.chunk_size {
+            return Err(invalid(format!(
+                "The peer splits a message of {} bytes into chunks of {} bytes but chunks \
+                 of {} bytes are expected",
+                len, self.chunk_size, expected.chunk_size
+            )));
+        }
+        if self.id != id {
+            return Err(invalid(format!(
+                "Expected message {} but received message {}",
+                id, self.id
+            )));
+        }
+        Ok(len)
+    }
+}
+
+/// Computes the checksum sent after a chunk.
+pub(super) fn checksum(chunk: &[u8]) -> [u8; CHECKSUM_LEN] {
+    crc32fast::hash(chunk).to_le_bytes()
+}
+
+/// Checks the checksum received after the chunk at `offset` against the
+/// checksum computed over its contents.
+pub(super) fn verify_checksum(
+    computed: u32,
+    received: &[u8; CHECKSUM_LEN],
+    offset: usize,
+) -> Result<(), io::Error> {
+    let received = u32::from_le_bytes(*received);
+    if computed != received {
+        return Err(invalid(format!(
+            "Checksum mismatch in the chunk at offset {}: received {:08x} but computed {:08x}",
+            offset, received, computed
+        )));
+    }
+    Ok(())
+}
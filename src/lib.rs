@@ -1,6 +1,20 @@
 //! This crate implements networking wrappers for measuring the amount of
 //! used communcation, efficiently sending/receiving large messages over
 //! slow networks, and using a single stream across multiple threads.
+//!
+//! The following optional features are available:
+//! * `ark-serialize`: sending and receiving values implementing
+//!   `CanonicalSerialize`/`CanonicalDeserialize` over an inverse multiplexer,
+//!   either compressed or uncompressed.
+//! * `async-std` (enabled by default): spawning the background task of a
+//!   `ThreadedReader` on the `async-std` runtime.
+//! * `metrics`: exporting communication metrics as JSON and in the Prometheus
+//!   text exposition format via the [`metrics`][mod@metrics] module.
+//! * `serde`: sending and receiving values implementing `Serialize`/
+//!   `DeserializeOwned` over an inverse multiplexer, encoded with `bincode`.
+//! * `tokio`: using tokio network streams via the [`compat`] module, tokio's
+//!   IO traits for `CountingIO`, and spawning the background task of a
+//!   `ThreadedReader` on a tokio runtime.
 #![warn(
     unused,
     future_incompatible,
@@ -10,8 +24,18 @@
     clippy::all
 )]
 
+#[cfg(feature = "tokio")]
+pub mod compat;
 pub mod counting;
+pub mod faulty;
 pub mod imux;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod recording;
+pub mod replay;
+mod rng;
+pub mod simulated;
+#[cfg(any(feature = "async-std", feature = "tokio"))]
 pub mod threaded;
 
 #[cfg(test)]
@@ -0,0 +1,240 @@
+//! This module defines the [`Metrics`] snapshot type for exporting the
+//! communication measured by [`CountingIO`] wrappers.
+//!
+//! A snapshot can be taken of a single [`CountingIO`] object or of all
+//! channels of an [`IMuxSync`]/[`IMuxAsync`] inverse multiplexer, and then
+//! serialised to JSON via [`Metrics::to_json`] or to the Prometheus text
+//! exposition format via [`Metrics::to_prometheus`].
+//!
+//! This module is only available with the `metrics` feature enabled.
+//!
+//! [`CountingIO`]: `crate::counting::CountingIO`
+//! [`IMuxSync`]: `crate::imux::IMuxSync`
+//! [`IMuxAsync`]: `crate::imux::IMuxAsync`
+
+use crate::{
+    counting::CountingIO,
+    imux::{IMuxAsync, IMuxSync},
+};
+use std::fmt::Write;
+
+/// A snapshot of the communication metrics of one or more channels.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metrics {
+    /// The metrics of each channel.
+    pub channels: Vec<ChannelMetrics>,
+}
+
+/// The communication metrics of a single channel.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChannelMetrics {
+    /// The number of bytes read.
+    pub bytes_read: u64,
+    /// The number of bytes written.
+    pub bytes_written: u64,
+    /// The number of communication rounds.
+    pub rounds: u64,
+    /// The number of read calls, if statistics are enabled.
+    pub read_calls: Option<u64>,
+    /// The number of write calls, if statistics are enabled.
+    pub write_calls: Option<u64>,
+    /// The time in seconds spent blocked in reads, if timing is enabled.
+    pub read_blocked_seconds: Option<f64>,
+    /// The time in seconds spent blocked in writes, if timing is enabled.
+    pub write_blocked_seconds: Option<f64>,
+}
+
+impl Metrics {
+    /// Returns the metrics aggregated across all channels.
+    ///
+    /// Byte and call counts are summed. Rounds and blocked times are combined
+    /// by taking the maximum, since the channels of an inverse multiplexer
+    /// operate concurrently.
+    pub fn aggregate(&self) -> ChannelMetrics {
+        let sum = |a: Option<u64>, b: Option<u64>| match (a, b) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+        let max = |a: Option<f64>, b: Option<f64>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        self.channels
+            .iter()
+            .fold(ChannelMetrics::default(), |acc, c| ChannelMetrics {
+                bytes_read: acc.bytes_read + c.bytes_read,
+                bytes_written: acc.bytes_written + c.bytes_written,
+                rounds: acc.rounds.max(c.rounds),
+                read_calls: sum(acc.read_calls, c.read_calls),
+                write_calls: sum(acc.write_calls, c.write_calls),
+                read_blocked_seconds: max(acc.read_blocked_seconds, c.read_blocked_seconds),
+                write_blocked_seconds: max(acc.write_blocked_seconds, c.write_blocked_seconds),
+            })
+    }
+
+    /// Serialises the metrics to a JSON object with a `channels` array holding
+    /// the metrics of each channel and an `aggregate` object.
+    pub fn to_json(&self) -> String {
+        let channels = self
+            .channels
+            .iter()
+            .map(ChannelMetrics::to_json)
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "{{\"channels\":[{}],\"aggregate\":{}}}",
+            channels,
+            self.aggregate().to_json()
+        )
+    }
+
+    /// Serialises the metrics to the Prometheus text exposition format.
+    ///
+    /// Every metric name is prefixed with `prefix`. Per-channel samples carry
+    /// a `channel` label holding the channel index and aggregate samples carry
+    /// the label `channel="all"`.
+    pub fn to_prometheus(&self, prefix: &str) -> String {
+        let aggregate = self.aggregate();
+        let samples = self
+            .channels
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (i.to_string(), c))
+            .chain(std::iter::once(("all".to_string(), &aggregate)))
+            .collect::<Vec<_>>();
+
+        let mut out = String::new();
+        for (name, kind, help, value) in ChannelMetrics::FIELDS {
+            let values = samples
+                .iter()
+                .filter_map(|(label, c)| value(c).map(|v| (label, v)))
+                .collect::<Vec<_>>();
+            if values.is_empty() {
+                continue;
+            }
+            let _ = writeln!(out, "# HELP {}_{} {}", prefix, name, help);
+            let _ = writeln!(out, "# TYPE {}_{} {}", prefix, name, kind);
+            for (label, v) in values {
+                let _ = writeln!(out, "{}_{}{{channel=\"{}\"}} {}", prefix, name, label, v);
+            }
+        }
+        out
+    }
+}
+
+/// The name, Prometheus type, description and accessor of an exported metric.
+type Field = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&ChannelMetrics) -> Option<f64>,
+);
+
+impl ChannelMetrics {
+    const FIELDS: [Field; 7] = [
+        (
+            "bytes_read_total",
+            "counter",
+            "Number of bytes read.",
+            |c| Some(c.bytes_read as f64),
+        ),
+        (
+            "bytes_written_total",
+            "counter",
+            "Number of bytes written.",
+            |c| Some(c.bytes_written as f64),
+        ),
+        (
+            "rounds_total",
+            "counter",
+            "Number of communication rounds.",
+            |c| Some(c.rounds as f64),
+        ),
+        (
+            "read_calls_total",
+            "counter",
+            "Number of read calls.",
+            |c| c.read_calls.map(|v| v as f64),
+        ),
+        (
+            "write_calls_total",
+            "counter",
+            "Number of write calls.",
+            |c| c.write_calls.map(|v| v as f64),
+        ),
+        (
+            "read_blocked_seconds_total",
+            "counter",
+            "Time spent blocked in reads.",
+            |c| c.read_blocked_seconds,
+        ),
+        (
+            "write_blocked_seconds_total",
+            "counter",
+            "Time spent blocked in writes.",
+            |c| c.write_blocked_seconds,
+        ),
+    ];
+
+    /// Returns the total communication amount in bytes.
+    pub fn count(&self) -> u64 {
+        self.bytes_read + self.bytes_written
+    }
+
+    /// Serialises the metrics to a JSON object. Metrics which weren't recorded
+    /// are serialised as `null`.
+    pub fn to_json(&self) -> String {
+        let fields = Self::FIELDS
+            .iter()
+            .map(|(name, _, _, value)| {
+                let name = name.trim_end_matches("_total");
+                match value(self) {
+                    Some(v) => format!("\"{}\":{}", name, v),
+                    None => format!("\"{}\":null", name),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        format!("{{{}}}", fields)
+    }
+}
+
+impl<I> From<&CountingIO<I>> for ChannelMetrics {
+    fn from(io: &CountingIO<I>) -> Self {
+        let stats = io.stats();
+        let timing = io.timing();
+        Self {
+            bytes_read: io.bytes_read(),
+            bytes_written: io.bytes_written(),
+            rounds: io.rounds(),
+            read_calls: stats.as_ref().map(|s| s.reads.calls),
+            write_calls: stats.as_ref().map(|s| s.writes.calls),
+            read_blocked_seconds: timing.as_ref().map(|t| t.read.blocked.as_secs_f64()),
+            write_blocked_seconds: timing.as_ref().map(|t| t.write.blocked.as_secs_f64()),
+        }
+    }
+}
+
+impl<I> From<&CountingIO<I>> for Metrics {
+    fn from(io: &CountingIO<I>) -> Self {
+        Self {
+            channels: vec![io.into()],
+        }
+    }
+}
+
+impl<I> From<&IMuxSync<CountingIO<I>>> for Metrics {
+    fn from(imux: &IMuxSync<CountingIO<I>>) -> Self {
+        Self {
+            channels: imux.get_ref().into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<I> From<&IMuxAsync<CountingIO<I>>> for Metrics {
+    fn from(imux: &IMuxAsync<CountingIO<I>>) -> Self {
+        Self {
+            channels: imux.get_ref().into_iter().map(Into::into).collect(),
+        }
+    }
+}
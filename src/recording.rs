@@ -0,0 +1,288 @@
+//! This module defines the [`RecordingIO<I>`] wrapper type for recording a
+//! transcript of all communication passing through a network connection.
+//!
+//! Every byte read or written through the wrapper is appended to a compact
+//! transcript along with its direction and the time at which it was
+//! transferred. A transcript can be loaded with [`Transcript::open`] in order
+//! to debug protocol desyncs between parties after the fact, or to archive the
+//! exact communication of a benchmark run.
+//!
+//! A transcript consists of the magic bytes `IOTR` and a version byte followed
+//! by one entry per successful read or write call. Each entry holds a
+//! direction byte, the number of microseconds since the previous entry, the
+//! number of bytes transferred and the bytes themselves, with both integers
+//! encoded as LEB128 varints.
+
+pub use crate::counting::Direction;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures::{io, AsyncRead, AsyncWrite};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// The magic bytes at the start of every transcript.
+const MAGIC: &[u8; 4] = b"IOTR";
+/// The current version of the transcript format.
+const VERSION: u8 = 1;
+
+/// A wrapper type for recording a transcript of a network connection.
+///
+/// `RecordingIO` wraps an [`Read`]/[`Write`]/[`AsyncRead`]/[`AsyncWrite`]
+/// object and tees every byte passed through it into a transcript sink.
+///
+/// `RecordingIO` implements the [`Read`]/[`Write`]/[`AsyncRead`]/[`AsyncWrite`]
+/// traits itself, so a wrapped object can be used the same as before. Errors
+/// writing to the transcript sink are returned from the call which transferred
+/// the data, after the data has already been transferred.
+pub struct RecordingIO<I, W: Write = BufWriter<File>> {
+    inner: I,
+    sink: W,
+    last: Instant,
+}
+
+/// A single read or write call in a transcript.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// The direction of the call.
+    pub direction: Direction,
+    /// The time of the call since the start of the recording.
+    pub timestamp: Duration,
+    /// The bytes transferred by the call.
+    pub data: Vec<u8>,
+}
+
+/// A transcript recorded by a [`RecordingIO`] object.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Transcript {
+    /// The recorded calls in the order they happened.
+    pub records: Vec<Record>,
+}
+
+impl<I> RecordingIO<I> {
+    /// Constructs a new `RecordingIO<I>` object which writes its transcript to
+    /// a newly created file at `path`.
+    pub fn create<P: AsRef<Path>>(inner: I, path: P) -> Result<Self, io::Error> {
+        Self::new(inner, BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<I, W: Write> RecordingIO<I, W> {
+    /// Constructs a new `RecordingIO<I, W>` object which writes its transcript
+    /// to `sink`.
+    pub fn new(inner: I, mut sink: W) -> Result<Self, io::Error> {
+        sink.write_all(MAGIC)?;
+        sink.write_all(&[VERSION])?;
+        Ok(Self {
+            inner,
+            sink,
+            last: Instant::now(),
+        })
+    }
+
+    /// Returns a reference to the wrapped object.
+    pub fn inner(&self) -> &I {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped object.
+    pub fn inner_mut(&mut self) -> &mut I {
+        &mut self.inner
+    }
+
+    /// Flushes the transcript and returns the wrapped object along with the
+    /// transcript sink.
+    pub fn finish(mut self) -> Result<(I, W), io::Error> {
+        self.sink.flush()?;
+        Ok((self.inner, self.sink))
+    }
+
+    fn record(&mut self, direction: Direction, data: &[u8]) -> Result<(), io::Error> {
+        let now = Instant::now();
+        let delta = now.duration_since(self.last).as_micros() as u64;
+        self.last = now;
+
+        let direction = match direction {
+            Direction::Read => 0,
+            Direction::Write => 1,
+        };
+        self.sink.write_all(&[direction])?;
+        write_varint(&mut self.sink, delta)?;
+        write_varint(&mut self.sink, data.len() as u64)?;
+        self.sink.write_all(data)
+    }
+}
+
+impl Transcript {
+    /// Loads the transcript stored in the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Loads a transcript from `reader`.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, io::Error> {
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a transcript: invalid magic bytes",
+            ));
+        }
+        if header[4] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported transcript version {}", header[4]),
+            ));
+        }
+
+        let mut records = Vec::new();
+        let mut timestamp = Duration::default();
+        let mut direction = [0u8; 1];
+        loop {
+            // A clean end of file is only allowed between records
+            if reader.read(&mut direction)? == 0 {
+                break;
+            }
+            let direction = match direction[0] {
+                0 => Direction::Read,
+                1 => Direction::Write,
+                d => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid transcript direction {}", d),
+                    ))
+                }
+            };
+            timestamp += Duration::from_micros(read_varint(&mut reader)?);
+            let len = read_varint(&mut reader)?;
+            let mut data = Vec::new();
+            reader.by_ref().take(len).read_to_end(&mut data)?;
+            if data.len() as u64 != len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            records.push(Record {
+                direction,
+                timestamp,
+                data,
+            });
+        }
+        Ok(Self { records })
+    }
+
+    /// Returns all bytes transferred in `direction`, concatenated in order.
+    pub fn bytes(&self, direction: Direction) -> Vec<u8> {
+        self.records
+            .iter()
+            .filter(|r| r.direction == direction)
+            .flat_map(|r| r.data.iter().copied())
+            .collect()
+    }
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> Result<(), io::Error> {
+    let mut buf = [0u8; 10];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    writer.write_all(&buf[..len])
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> Result<u64, io::Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Invalid transcript varint",
+    ))
+}
+
+impl<I: Read, W: Write> Read for RecordingIO<I, W> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let bytes = self.inner.read(buf)?;
+        if bytes > 0 {
+            self.record(Direction::Read, &buf[..bytes])?;
+        }
+        Ok(bytes)
+    }
+}
+
+impl<I: Write, W: Write> Write for RecordingIO<I, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        let bytes = self.inner.write(buf)?;
+        if bytes > 0 {
+            self.record(Direction::Write, &buf[..bytes])?;
+        }
+        Ok(bytes)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.inner.flush()?;
+        self.sink.flush()
+    }
+}
+
+impl<I: AsyncRead + Unpin, W: Write + Unpin> AsyncRead for RecordingIO<I, W> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+        let ret = Pin::new(&mut this.inner).poll_read(ctx, buf);
+        if let Poll::Ready(Ok(bytes)) = ret {
+            if bytes > 0 {
+                this.record(Direction::Read, &buf[..bytes])?;
+            }
+        }
+        ret
+    }
+}
+
+impl<I: AsyncWrite + Unpin, W: Write + Unpin> AsyncWrite for RecordingIO<I, W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+        let ret = Pin::new(&mut this.inner).poll_write(ctx, buf);
+        if let Poll::Ready(Ok(bytes)) = ret {
+            if bytes > 0 {
+                this.record(Direction::Write, &buf[..bytes])?;
+            }
+        }
+        ret
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = self.get_mut();
+        futures::ready!(Pin::new(&mut this.inner).poll_flush(ctx))?;
+        Poll::Ready(this.sink.flush())
+    }
+
+    fn poll_close(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = self.get_mut();
+        futures::ready!(Pin::new(&mut this.inner).poll_close(ctx))?;
+        Poll::Ready(this.sink.flush())
+    }
+}
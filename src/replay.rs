@@ -0,0 +1,148 @@
+//! This module defines the [`ReplayIO`] transport for replaying a transcript
+//! recorded by a [`RecordingIO`] wrapper.
+//!
+//! A [`ReplayIO`] object plays back the peer's side of a recorded session: the
+//! bytes returned from reads are exactly the bytes that were read during the
+//! recording. At the same time, every byte written is checked against the
+//! bytes written during the recording, and the first divergence is reported.
+//! This allows running one party of a two-party protocol offline, without a
+//! live peer.
+//!
+//! [`RecordingIO`]: `crate::recording::RecordingIO`
+
+use crate::recording::{Direction, Transcript};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures::{io, AsyncRead, AsyncWrite};
+use std::{
+    io::{Read, Write},
+    path::Path,
+};
+
+/// A transport which replays a recorded transcript.
+///
+/// `ReplayIO` implements the [`Read`]/[`Write`]/[`AsyncRead`]/[`AsyncWrite`]
+/// traits, so it can be used in place of the network stream that was recorded.
+/// Reads return end of file once all recorded reads have been replayed.
+///
+/// Writes which don't match the recording fail with an
+/// [`io::ErrorKind::InvalidData`] error and record the offset of the first
+/// mismatching byte, available via [`ReplayIO::divergence`]. All writes fail
+/// after a divergence.
+pub struct ReplayIO {
+    incoming: Vec<u8>,
+    read_pos: usize,
+    expected: Vec<u8>,
+    write_pos: usize,
+    divergence: Option<u64>,
+}
+
+impl ReplayIO {
+    /// Constructs a new `ReplayIO` object replaying `transcript`.
+    pub fn new(transcript: &Transcript) -> Self {
+        Self {
+            incoming: transcript.bytes(Direction::Read),
+            read_pos: 0,
+            expected: transcript.bytes(Direction::Write),
+            write_pos: 0,
+            divergence: None,
+        }
+    }
+
+    /// Constructs a new `ReplayIO` object replaying the transcript stored in
+    /// the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        Ok(Self::new(&Transcript::open(path)?))
+    }
+
+    /// Returns the offset in the written byte stream at which the writes first
+    /// diverged from the recording, if they have.
+    pub fn divergence(&self) -> Option<u64> {
+        self.divergence
+    }
+
+    /// Returns the number of recorded bytes that have not been read yet.
+    pub fn remaining_read(&self) -> usize {
+        self.incoming.len() - self.read_pos
+    }
+
+    /// Returns the number of recorded bytes that have not been written yet.
+    pub fn remaining_written(&self) -> usize {
+        self.expected.len() - self.write_pos
+    }
+
+    /// Returns `true` if the whole recording has been replayed without any
+    /// divergence.
+    pub fn is_complete(&self) -> bool {
+        self.divergence.is_none() && self.remaining_read() == 0 && self.remaining_written() == 0
+    }
+
+    fn diverged(offset: u64) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Write diverged from the transcript at offset {}", offset),
+        )
+    }
+}
+
+impl Read for ReplayIO {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let bytes = buf.len().min(self.remaining_read());
+        buf[..bytes].copy_from_slice(&self.incoming[self.read_pos..self.read_pos + bytes]);
+        self.read_pos += bytes;
+        Ok(bytes)
+    }
+}
+
+impl Write for ReplayIO {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        if let Some(offset) = self.divergence {
+            return Err(Self::diverged(offset));
+        }
+        let expected = &self.expected[self.write_pos..];
+        let mismatch = buf
+            .iter()
+            .zip(expected.iter())
+            .position(|(a, b)| a != b)
+            .or(Some(expected.len()).filter(|&len| buf.len() > len));
+        if let Some(i) = mismatch {
+            let offset = (self.write_pos + i) as u64;
+            self.divergence = Some(offset);
+            return Err(Self::diverged(offset));
+        }
+        self.write_pos += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for ReplayIO {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _ctx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        Poll::Ready(Read::read(self.get_mut(), buf))
+    }
+}
+
+impl AsyncWrite for ReplayIO {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        Poll::Ready(Write::write(self.get_mut(), buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,247 @@
+//! This module defines the [`SimulatedLink<I>`] wrapper type for simulating
+//! slow network conditions on fast local connections.
+//!
+//! [`SimulatedLink<I>`] injects a configurable one-way latency, jitter and
+//! bandwidth cap into the writes of the object it wraps. This makes it
+//! possible to reproduce LAN/WAN measurements, e.g. for the inverse
+//! multiplexers in [`imux`], on a single machine over loopback.
+//!
+//! The link is simulated on the sending side: the first write of every flight
+//! of writes (i.e. the first write after a read) is delayed by the one-way
+//! latency plus a random jitter, and every write is held back for its
+//! transmission time under the bandwidth cap before being passed on. Both
+//! endpoints of a connection should wrap their streams in order to shape
+//! traffic in both directions.
+//!
+//! The bandwidth cap applies to each wrapped connection separately, which
+//! models the per-connection throughput limit imposed by TCP window sizes on
+//! high-latency links.
+//!
+//! [`imux`]: `crate::imux`
+
+use crate::rng::XorShift;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures::{io, ready, AsyncRead, AsyncWrite};
+use futures_timer::Delay;
+use std::{
+    io::{Read, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The maximum number of bytes passed on to the wrapped object in a single
+/// write when the bandwidth is capped.
+const MAX_BURST: usize = 65536;
+
+/// The network conditions simulated by a [`SimulatedLink`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LinkConfig {
+    /// The one-way latency added to each flight of writes.
+    pub latency: Duration,
+    /// The maximum random jitter added to the latency. The jitter of each
+    /// flight is drawn uniformly from `[0, jitter]`.
+    pub jitter: Duration,
+    /// The maximum throughput of the connection in bytes per second, or `None`
+    /// for an unlimited throughput.
+    pub bandwidth: Option<u64>,
+    /// The seed for the random jitter.
+    pub seed: u64,
+}
+
+impl LinkConfig {
+    /// Returns the conditions of a typical LAN: 0.1ms one-way latency and a
+    /// 1Gbps bandwidth cap.
+    pub fn lan() -> Self {
+        Self {
+            latency: Duration::from_micros(100),
+            bandwidth: Some(125_000_000),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the conditions of a typical WAN: 20ms one-way latency and a
+    /// 100Mbps bandwidth cap.
+    pub fn wan() -> Self {
+        Self {
+            latency: Duration::from_millis(20),
+            bandwidth: Some(12_500_000),
+            ..Self::default()
+        }
+    }
+}
+
+/// A wrapper type for simulating network conditions on a connection.
+///
+/// `SimulatedLink` implements the
+/// [`Read`]/[`Write`]/[`AsyncRead`]/[`AsyncWrite`] traits itself, so a wrapped
+/// object can be used the same as before. The synchronous traits block the
+/// calling thread in order to delay writes, while the asynchronous traits use
+/// a runtime-agnostic timer.
+pub struct SimulatedLink<I> {
+    inner: I,
+    config: LinkConfig,
+    rng: XorShift,
+    // Whether the next write starts a new flight
+    new_flight: bool,
+    // The time at which the previous write finished transmitting
+    next_free: Instant,
+    // The time at which the current write finishes transmitting along with
+    // its length, once it has been scheduled. If the wrapped object accepted
+    // only part of a write, the rest has already been transmitted and is
+    // passed on by the next write without delay
+    scheduled: Option<(Instant, usize)>,
+    delay: Option<Delay>,
+}
+
+impl<I> SimulatedLink<I> {
+    /// Constructs a new `SimulatedLink<I>` object simulating `config`.
+    pub fn new(inner: I, config: LinkConfig) -> Self {
+        let rng = XorShift::new(config.seed);
+        Self {
+            inner,
+            config,
+            rng,
+            new_flight: true,
+            next_free: Instant::now(),
+            scheduled: None,
+            delay: None,
+        }
+    }
+
+    /// Returns the simulated network conditions.
+    pub fn config(&self) -> &LinkConfig {
+        &self.config
+    }
+
+    /// Returns a reference to the wrapped object.
+    pub fn inner(&self) -> &I {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped object.
+    pub fn inner_mut(&mut self) -> &mut I {
+        &mut self.inner
+    }
+
+    /// Consumes the wrapper and returns the wrapped object.
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+
+    /// Schedules a write of at most `len` bytes, returning the time at which
+    /// it may be passed on to the wrapped object along with the number of
+    /// bytes to pass on.
+    fn schedule(&mut self, len: usize) -> (Instant, usize) {
+        if let Some((ready_at, bytes)) = self.scheduled {
+            return (ready_at, bytes.min(len));
+        }
+        let mut start = Instant::now();
+        if self.new_flight {
+            self.new_flight = false;
+            let max_jitter = self.config.jitter.as_nanos().min(u64::MAX as u128) as u64;
+            let jitter = self.rng.below(max_jitter.saturating_add(1));
+            start += self.config.latency + Duration::from_nanos(jitter);
+        }
+        let start = start.max(self.next_free);
+        let scheduled = match self.config.bandwidth {
+            Some(bandwidth) => {
+                let bytes = len.min(MAX_BURST);
+                let transmission = bytes as f64 / bandwidth.max(1) as f64;
+                (start + Duration::from_secs_f64(transmission), bytes)
+            }
+            None => (start, len),
+        };
+        self.scheduled = Some(scheduled);
+        scheduled
+    }
+
+    /// Completes the scheduled write, of which the wrapped object accepted
+    /// `written` bytes.
+    fn record_written(&mut self, written: usize) {
+        if let Some((ready_at, bytes)) = self.scheduled.take() {
+            self.next_free = ready_at;
+            if written < bytes {
+                self.scheduled = Some((ready_at, bytes - written));
+            }
+        }
+    }
+
+    fn record_read(&mut self, bytes: usize) {
+        if bytes > 0 {
+            self.new_flight = true;
+        }
+    }
+}
+
+impl<I: Read> Read for SimulatedLink<I> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let bytes = self.inner.read(buf)?;
+        self.record_read(bytes);
+        Ok(bytes)
+    }
+}
+
+impl<I: Write> Write for SimulatedLink<I> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        let (ready_at, bytes) = self.schedule(buf.len());
+        let now = Instant::now();
+        if ready_at > now {
+            thread::sleep(ready_at - now);
+        }
+        let ret = self.inner.write(&buf[..bytes]);
+        self.record_written(*ret.as_ref().unwrap_or(&0));
+        ret
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for SimulatedLink<I> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+        let bytes = ready!(Pin::new(&mut this.inner).poll_read(ctx, buf))?;
+        this.record_read(bytes);
+        Poll::Ready(Ok(bytes))
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for SimulatedLink<I> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+        let scheduled = this.scheduled.is_some();
+        let (ready_at, bytes) = this.schedule(buf.len());
+        if !scheduled {
+            let now = Instant::now();
+            if ready_at > now {
+                this.delay = Some(Delay::new(ready_at - now));
+            }
+        }
+        if let Some(delay) = &mut this.delay {
+            ready!(Pin::new(delay).poll(ctx));
+            this.delay = None;
+        }
+        let ret = ready!(Pin::new(&mut this.inner).poll_write(ctx, &buf[..bytes]));
+        this.record_written(*ret.as_ref().unwrap_or(&0));
+        Poll::Ready(ret)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(ctx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(ctx)
+    }
+}
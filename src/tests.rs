@@ -0,0 +1,1788 @@
+mod counting {
+    use crate::counting::CountingIO;
+    use futures::{executor::block_on, io::Cursor, AsyncReadExt, AsyncWriteExt};
+    use std::time::Duration;
+
+    #[test]
+    fn reads_and_writes_are_counted_separately() {
+        let mut io = CountingIO::new(Cursor::new(vec![0u8; 10]));
+        block_on(io.read_exact(&mut [0; 4])).unwrap();
+        block_on(io.write_all(&[1; 3])).unwrap();
+        assert_eq!((io.bytes_read(), io.bytes_written(), io.count()), (4, 3, 7));
+        io.reset_read();
+        assert_eq!((io.bytes_read(), io.bytes_written(), io.count()), (0, 3, 3));
+        io.reset_written();
+        assert_eq!(io.count(), 0);
+
+        let channels = (0..2).map(|_| CountingIO::new(Vec::new())).collect();
+        let mut imux = crate::imux::IMuxSync::new(channels);
+        imux.write(&[1; 100]).unwrap();
+        // The message is sent with a header of 36 bytes
+        assert_eq!((imux.bytes_read(), imux.bytes_written()), (0, 136));
+        assert_eq!(imux.count(), 136);
+    }
+
+    #[test]
+    fn shared_counter_is_visible_to_handles() {
+        assert!(CountingIO::new(Vec::<u8>::new()).handle().is_none());
+        let mut io = CountingIO::with_shared_counter(Cursor::new(vec![0u8; 10]));
+        let handle = io.handle().unwrap();
+        let writer = std::thread::spawn(move || {
+            block_on(io.write_all(&[1; 6])).unwrap();
+            io
+        });
+        let mut io = writer.join().unwrap();
+        assert_eq!((handle.bytes_read(), handle.bytes_written()), (0, 6));
+        block_on(io.read_exact(&mut [0; 4])).unwrap();
+        let other = handle.clone();
+        assert_eq!((other.count(), other.rounds()), (10, 2));
+        other.reset_written();
+        assert_eq!((io.bytes_read(), io.bytes_written()), (4, 0));
+        handle.reset();
+        assert_eq!((io.count(), io.rounds()), (0, 0));
+    }
+
+    #[test]
+    fn rounds_count_direction_switches() {
+        let mut io = CountingIO::new(Cursor::new(vec![0u8; 100]));
+        block_on(io.write_all(&[1; 10])).unwrap();
+        block_on(io.write_all(&[1; 10])).unwrap();
+        assert_eq!(io.rounds(), 1);
+        block_on(io.read_exact(&mut [0; 10])).unwrap();
+        block_on(io.write_all(&[1; 10])).unwrap();
+        assert_eq!(io.rounds(), 3);
+        io.reset_rounds();
+        assert_eq!((io.rounds(), io.count()), (0, 40));
+    }
+
+    #[test]
+    fn messages_across_channels_count_as_one_round() {
+        use crate::imux::{IMuxAsync, IMuxSync};
+
+        let channels = (0..16).map(|_| CountingIO::new(Vec::new())).collect();
+        let mut imux = IMuxSync::new(channels);
+        imux.write(&vec![1; 1 << 20]).unwrap();
+        imux.write(&vec![2; 1 << 20]).unwrap();
+        assert_eq!(imux.rounds(), 1);
+        assert!(imux.get_ref().iter().all(|c| c.rounds() == 1));
+
+        let streams = imux
+            .into_inner()
+            .into_iter()
+            .map(|c| Cursor::new(c.into_inner()));
+        let mut imux = IMuxAsync::new(streams.map(CountingIO::new).collect());
+        block_on(imux.read()).unwrap();
+        block_on(imux.read()).unwrap();
+        assert_eq!(imux.rounds(), 1);
+    }
+
+    /// A writer which counts its calls to `write_vectored`.
+    #[derive(Default)]
+    struct Vectored {
+        data: Vec<u8>,
+        vectored_calls: usize,
+    }
+
+    impl std::io::Write for Vectored {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn write_vectored(&mut self, bufs: &[std::io::IoSlice<'_>]) -> std::io::Result<usize> {
+            self.vectored_calls += 1;
+            bufs.iter().for_each(|buf| self.data.extend_from_slice(buf));
+            Ok(bufs.iter().map(|buf| buf.len()).sum())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn vectored_io_is_passed_through() {
+        use std::io::{IoSlice, IoSliceMut, Read, Write};
+
+        let mut io = CountingIO::new(Vectored::default());
+        io.enable_stats();
+        let bufs = [IoSlice::new(b"abc"), IoSlice::new(b"defg")];
+        assert_eq!(io.write_vectored(&bufs).unwrap(), 7);
+        assert_eq!(
+            (io.bytes_written(), io.stats().unwrap().writes.calls),
+            (7, 1)
+        );
+        assert_eq!(io.inner().vectored_calls, 1);
+        assert_eq!(io.inner().data, b"abcdefg");
+
+        let mut io = CountingIO::new(std::io::Cursor::new(b"abcdefgh".to_vec()));
+        let (mut a, mut b) = ([0u8; 3], [0u8; 4]);
+        let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+        assert_eq!(io.read_vectored(&mut bufs).unwrap(), 7);
+        assert_eq!((&a, &b, io.bytes_read()), (b"abc", b"defg", 7));
+    }
+
+    #[test]
+    fn buffered_reads_count_consumed_bytes() {
+        use std::io::BufRead;
+
+        let mut io = CountingIO::new(std::io::BufReader::new(&b"hello\nworld\n"[..]));
+        let mut line = String::new();
+        io.read_line(&mut line).unwrap();
+        assert_eq!((line.as_str(), io.bytes_read()), ("hello\n", 6));
+        assert_eq!(io.fill_buf().unwrap(), b"world\n");
+        assert_eq!(io.bytes_read(), 6);
+        io.consume(2);
+        assert_eq!(io.bytes_read(), 8);
+    }
+
+    #[test]
+    fn async_buffered_reads_count_consumed_bytes() {
+        use futures::AsyncBufReadExt;
+
+        let mut io = CountingIO::new(Cursor::new(b"hello\nworld\n".to_vec()));
+        let mut line = String::new();
+        block_on(io.read_line(&mut line)).unwrap();
+        assert_eq!((line.as_str(), io.bytes_read()), ("hello\n", 6));
+    }
+
+    fn phase(label: &str, bytes: (u64, u64), rounds: u64) -> crate::counting::PhaseReport {
+        crate::counting::PhaseReport {
+            label: label.to_string(),
+            bytes_read: bytes.0,
+            bytes_written: bytes.1,
+            rounds,
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn phases_are_nested_and_reentered() {
+        let mut io = CountingIO::new(Cursor::new(vec![0u8; 100]));
+        {
+            let _offline = io.phase("offline");
+            block_on(io.write_all(&[1; 10])).unwrap();
+            let _setup = io.phase("setup");
+            block_on(io.read_exact(&mut [0; 5])).unwrap();
+        }
+        {
+            let _offline = io.phase("offline");
+            block_on(io.write_all(&[1; 2])).unwrap();
+        }
+        let online = io.phase("online");
+        block_on(io.write_all(&[1; 3])).unwrap();
+        drop(online);
+        block_on(io.write_all(&[1; 4])).unwrap();
+
+        let mut offline = phase("offline", (5, 12), 3);
+        offline.children.push(phase("setup", (5, 0), 1));
+        assert_eq!(io.phases(), [offline, phase("online", (0, 3), 1)]);
+        assert_eq!(io.count(), 24);
+    }
+
+    #[test]
+    fn phases_are_merged_across_channels() {
+        let channels = (0..4).map(|_| CountingIO::new(Vec::new())).collect();
+        let mut imux = crate::imux::IMuxSync::new(channels);
+        let send = imux.phase("send");
+        imux.write(&vec![1; 1 << 20]).unwrap();
+        drop(send);
+        imux.write(&[1; 10]).unwrap();
+        assert_eq!(imux.phases(), [phase("send", (0, (1 << 20) + 36), 1)]);
+
+        let mut a = phase("a", (1, 2), 2);
+        a.children.push(phase("nested", (1, 0), 1));
+        let merged = crate::counting::PhaseReport::merge(vec![
+            vec![a],
+            vec![phase("a", (3, 4), 3), phase("b", (5, 6), 1)],
+        ]);
+        let mut a = phase("a", (4, 6), 3);
+        a.children.push(phase("nested", (1, 0), 1));
+        assert_eq!(merged, [a, phase("b", (5, 6), 1)]);
+    }
+
+    #[test]
+    fn stats_record_call_sizes() {
+        use crate::counting::CallStats;
+
+        let mut io = CountingIO::new(Cursor::new(vec![0u8; 100]));
+        block_on(io.write_all(&[1; 1])).unwrap();
+        assert_eq!(io.stats(), None);
+        io.enable_stats();
+        for size in [1, 3, 4, 100] {
+            block_on(io.write_all(&vec![1; size])).unwrap();
+        }
+        io.inner_mut().set_position(0);
+        block_on(io.read(&mut [0; 10])).unwrap();
+
+        let stats = io.stats().unwrap();
+        let writes = &stats.writes;
+        assert_eq!(
+            (writes.calls, writes.bytes, writes.min, writes.max),
+            (4, 108, 1, 100)
+        );
+        assert_eq!(writes.mean(), 27.0);
+        assert_eq!(&writes.histogram[..8], [0, 1, 1, 1, 0, 0, 0, 1]);
+        assert_eq!(CallStats::bucket(100), 7);
+        assert_eq!((stats.reads.calls, stats.reads.bytes), (1, 10));
+
+        let mut combined = stats.clone();
+        combined.combine(&stats);
+        assert_eq!((combined.writes.calls, combined.writes.min), (8, 1));
+        assert_eq!(combined.writes.histogram[7], 2);
+
+        io.reset_stats();
+        let stats = io.stats().unwrap();
+        assert_eq!((stats.writes.calls, stats.writes.mean()), (0, 0.0));
+    }
+
+    #[test]
+    fn throughput_of_reads_which_never_block() {
+        let mut io = CountingIO::new(Cursor::new(vec![0u8; 1 << 16]));
+        io.enable_timing();
+        let mut buf = vec![0u8; 1 << 15];
+        block_on(io.read_exact(&mut buf)).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        block_on(io.read_exact(&mut buf)).unwrap();
+
+        let read = io.timing().unwrap().read;
+        assert_eq!(read.blocked, Duration::default());
+        assert!(read.span() >= Duration::from_millis(10));
+        assert!(read.throughput().unwrap() <= (1 << 16) as f64 / 0.01);
+    }
+
+    mod budget {
+        use super::*;
+        use crate::{
+            counting::{Budget, BudgetExceeded, Direction},
+            imux::IMuxSync,
+        };
+        use std::io::ErrorKind;
+
+        fn reads(limit: u64) -> Budget {
+            Budget {
+                read: Some(limit),
+                write: None,
+            }
+        }
+
+        #[test]
+        fn reads_are_limited_to_the_budget() {
+            let mut io = CountingIO::new(Cursor::new(vec![0u8; 100]));
+            io.set_budget(reads(10));
+            let mut buf = [0u8; 8];
+            assert_eq!(block_on(io.read(&mut buf)).unwrap(), 8);
+            assert_eq!(io.remaining_budget(), Some(reads(2)));
+            // A read which only partially fits transfers the bytes which fit
+            assert_eq!(block_on(io.read(&mut buf)).unwrap(), 2);
+            assert_eq!(io.remaining_budget(), Some(reads(0)));
+            let err = block_on(io.read(&mut buf)).unwrap_err();
+            assert_eq!(
+                BudgetExceeded::from_io_error(&err),
+                Some(&BudgetExceeded {
+                    direction: Direction::Read,
+                    limit: 10,
+                })
+            );
+            assert_eq!(io.bytes_read(), 10);
+        }
+
+        #[test]
+        fn unused_reservations_are_released() {
+            let mut io = CountingIO::new(Cursor::new(vec![0u8; 3]));
+            io.set_budget(reads(10));
+            let mut buf = [0u8; 8];
+            assert_eq!(block_on(io.read(&mut buf)).unwrap(), 3);
+            assert_eq!(io.remaining_budget(), Some(reads(7)));
+            assert_eq!(block_on(io.read(&mut buf)).unwrap(), 0);
+            assert_eq!(io.remaining_budget(), Some(reads(7)));
+            io.clear_budget();
+            assert_eq!(io.remaining_budget(), None);
+        }
+
+        #[test]
+        fn exceeded_budget_is_wrapped_in_io_error() {
+            let budget = Budget {
+                read: None,
+                write: Some(5),
+            };
+            let mut io = CountingIO::new(Vec::new());
+            io.set_budget(budget);
+            let err = std::io::Write::write_all(&mut io, b"too long").unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Other);
+            let exceeded = err.get_ref().unwrap().downcast_ref::<BudgetExceeded>();
+            assert_eq!(
+                exceeded,
+                Some(&BudgetExceeded {
+                    direction: Direction::Write,
+                    limit: 5,
+                })
+            );
+            assert_eq!(
+                err.to_string(),
+                "Communication budget of 5 bytes exceeded by write"
+            );
+            assert_eq!(io.into_inner(), b"too l");
+            let other = std::io::Error::other("not a budget");
+            assert_eq!(BudgetExceeded::from_io_error(&other), None);
+        }
+
+        #[test]
+        fn budget_is_shared_by_channels() {
+            let budget = Budget {
+                read: None,
+                write: Some(100),
+            };
+            let channels = (0..2).map(|_| CountingIO::new(Vec::new())).collect();
+            let mut imux = IMuxSync::new(channels);
+            imux.set_budget(budget);
+            // The message is sent with a header of 36 bytes
+            imux.write(&[1; 50]).unwrap();
+            let remaining = Budget {
+                read: None,
+                write: Some(14),
+            };
+            assert_eq!(imux.remaining_budget(), Some(remaining));
+            for channel in imux.get_ref() {
+                assert_eq!(channel.remaining_budget(), Some(remaining));
+            }
+            let err = imux.write(&[1; 50]).unwrap_err();
+            assert!(BudgetExceeded::from_io_error(&err).is_some(), "{}", err);
+            assert_eq!(imux.count(), 100);
+        }
+    }
+}
+
+mod simulated {
+    use crate::simulated::{LinkConfig, SimulatedLink};
+    use std::{
+        io::{self, Write},
+        time::{Duration, Instant},
+    };
+
+    /// A writer which accepts at most 1 KiB per write.
+    struct Partial(Vec<u8>);
+
+    impl Write for Partial {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(1024);
+            self.0.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn partial_writes_are_charged_once() {
+        let config = LinkConfig {
+            bandwidth: Some(1_000_000),
+            ..LinkConfig::default()
+        };
+        let mut link = SimulatedLink::new(Partial(Vec::new()), config);
+        let start = Instant::now();
+        link.write_all(&[1u8; 65536]).unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(link.inner().0.len(), 65536);
+        assert!(elapsed >= Duration::from_millis(60), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(500), "{:?}", elapsed);
+    }
+}
+
+mod imux {
+    use crate::imux::{IMuxAsync, IMuxConfig, IMuxSync, Schedule};
+    use futures::{executor::block_on, AsyncReadExt, AsyncWriteExt};
+    use std::{
+        io::{self, Cursor, ErrorKind, Read, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    /// Returns the bytes sent on each of `channels` channels for `messages`
+    /// sent with `config`.
+    fn send(config: IMuxConfig, channels: usize, messages: &[&[u8]]) -> Vec<Vec<u8>> {
+        let mut imux = IMuxSync::with_config(vec![Vec::new(); channels], config);
+        for message in messages {
+            imux.write(message).unwrap();
+        }
+        imux.into_inner()
+    }
+
+    /// Returns the header of a message of `len` bytes sent with `config`
+    /// across `channels` channels.
+    fn header(config: IMuxConfig, channels: usize, len: usize) -> Vec<u8> {
+        send(config, channels, &[&vec![0u8; len]]).swap_remove(0)[..36].to_vec()
+    }
+
+    /// Receives a message from `streams` with `config`, expecting it to be
+    /// rejected as invalid with an error containing `expected`.
+    fn rejects(config: IMuxConfig, streams: Vec<Vec<u8>>, expected: &str) {
+        let streams = streams.into_iter().map(Cursor::new).collect();
+        let err = IMuxSync::with_config(streams, config).read().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", err);
+        assert!(err.to_string().contains(expected), "{}", err);
+    }
+
+    /// Encodes a chunk of the adaptive schedule at `offset`, or the end
+    /// marker if `data` is empty.
+    fn adaptive_chunk(offset: u64, data: &[u8]) -> Vec<u8> {
+        let mut chunk = offset.to_le_bytes().to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    fn adaptive(chunk_size: usize) -> IMuxConfig {
+        IMuxConfig::new().schedule(Schedule::Adaptive { chunk_size })
+    }
+
+    #[test]
+    fn adaptive_chunk_size_fits_chunk_header() {
+        assert_eq!(adaptive(usize::MAX), adaptive(u32::MAX as usize));
+        assert_eq!(adaptive(0), adaptive(1));
+    }
+
+    #[test]
+    fn adaptive_duplicate_chunk() {
+        let config = adaptive(4);
+        let mut stream = header(config, 1, 8);
+        stream.extend(adaptive_chunk(0, &[1; 4]));
+        stream.extend(adaptive_chunk(0, &[2; 4]));
+        stream.extend(adaptive_chunk(0, &[]));
+        let mut imux = IMuxSync::with_config(vec![Cursor::new(stream)], config);
+        let mut buf = [0u8; 8];
+        let err = imux.read_into(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("twice"), "{}", err);
+    }
+
+    #[test]
+    fn adaptive_overlapping_chunk() {
+        let config = adaptive(4);
+        let mut stream = header(config, 1, 8);
+        stream.extend(adaptive_chunk(0, &[1; 4]));
+        stream.extend(adaptive_chunk(2, &[2; 4]));
+        stream.extend(adaptive_chunk(0, &[]));
+        let mut imux = IMuxSync::with_config(vec![Cursor::new(stream)], config);
+        let err = imux.read().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("offset 2"), "{}", err);
+    }
+
+    #[test]
+    fn adaptive_forged_length_doesnt_allocate() {
+        let config = adaptive(1);
+        let len = 1u64 << 40;
+        let mut stream = header(config, 1, 1);
+        stream[28..36].copy_from_slice(&len.to_le_bytes());
+        stream.extend(adaptive_chunk(len - 1, &[1]));
+        stream.extend(adaptive_chunk(0, &[]));
+        let mut imux = IMuxSync::with_config(vec![Cursor::new(stream)], config);
+        let mut buf = Vec::new();
+        let err = imux.read_into_vec(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(buf.capacity() <= 1 << 21, "{}", buf.capacity());
+    }
+
+    #[test]
+    fn static_forged_length_doesnt_allocate() {
+        let config = IMuxConfig::new().min_chunk_size(1).max_chunk_size(1);
+        let mut stream = header(config, 1, 1);
+        stream[28..36].copy_from_slice(&(1u64 << 34).to_le_bytes());
+        stream.extend_from_slice(&[1; 100]);
+
+        let mut imux = IMuxSync::with_config(vec![Cursor::new(stream.clone())], config);
+        let err = imux.read().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "{}", err);
+
+        let channels = vec![futures::io::Cursor::new(stream)];
+        let mut imux = IMuxAsync::with_config(channels, config);
+        let err = block_on(imux.read()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "{}", err);
+    }
+
+    /// A channel which fails unless all channels are being read from at once,
+    /// after the first `free` bytes.
+    struct Concurrent {
+        data: Cursor<Vec<u8>>,
+        free: usize,
+        started: Arc<AtomicUsize>,
+        channels: usize,
+    }
+
+    impl Read for Concurrent {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let pos = self.data.position() as usize;
+            if pos >= self.free {
+                if pos == self.free {
+                    self.started.fetch_add(1, Ordering::SeqCst);
+                }
+                let deadline = Instant::now() + Duration::from_secs(5);
+                while self.started.load(Ordering::SeqCst) < self.channels {
+                    if Instant::now() > deadline {
+                        return Err(io::Error::new(ErrorKind::TimedOut, "channels idle"));
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+            let len = if pos < self.free {
+                buf.len().min(self.free - pos)
+            } else {
+                buf.len()
+            };
+            self.data.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn read_receives_on_all_channels_at_once() {
+        let channels = 4;
+        let message = (0..1u32 << 22).map(|i| i as u8).collect::<Vec<_>>();
+        let mut imux = IMuxSync::new(vec![Vec::new(); channels]);
+        imux.write(&message).unwrap();
+        let started = Arc::new(AtomicUsize::new(0));
+        let streams = imux
+            .into_inner()
+            .into_iter()
+            .enumerate()
+            .map(|(i, data)| Concurrent {
+                data: Cursor::new(data),
+                free: if i == 0 { 36 } else { 0 },
+                started: started.clone(),
+                channels,
+            })
+            .collect();
+        assert_eq!(IMuxSync::new(streams).read().unwrap(), message);
+    }
+
+    #[test]
+    fn read_into_vec_reuses_buffer() {
+        let long = vec![7u8; 1000];
+        let short = vec![3u8; 300];
+        for config in configs() {
+            let streams = send(config, 3, &[&long, &short, &long]);
+            let mut buf = Vec::with_capacity(long.len());
+            let (ptr, capacity) = (buf.as_ptr(), buf.capacity());
+
+            let channels = streams.iter().cloned().map(Cursor::new).collect();
+            let mut imux = IMuxSync::with_config(channels, config);
+            for expected in [&long, &short, &long] {
+                imux.read_into_vec(&mut buf).unwrap();
+                assert_eq!(&buf, expected);
+                assert_eq!((buf.as_ptr(), buf.capacity()), (ptr, capacity));
+            }
+
+            let channels = streams.into_iter().map(futures::io::Cursor::new).collect();
+            let mut imux = IMuxAsync::with_config(channels, config);
+            for expected in [&long, &short, &long] {
+                block_on(imux.read_into_vec(&mut buf)).unwrap();
+                assert_eq!(&buf, expected);
+                assert_eq!((buf.as_ptr(), buf.capacity()), (ptr, capacity));
+            }
+        }
+    }
+
+    #[test]
+    fn per_channel_counts_and_skew() {
+        use crate::{counting::CountingIO, imux::ChannelCount};
+
+        let config = IMuxConfig::new().min_chunk_size(1000);
+        let channels = (0..4).map(|_| CountingIO::new(Vec::new())).collect();
+        let mut imux = IMuxSync::with_config(channels, config);
+        imux.write(&[1; 4000]).unwrap();
+        let counts = imux.per_channel_counts();
+        let written = counts.iter().map(|c| c.bytes_written).collect::<Vec<_>>();
+        assert_eq!(written, [1036, 1000, 1000, 1000]);
+        assert!(counts.iter().all(|c| c.bytes_read == 0));
+        assert_eq!(imux.count_skew(), 1036.0 * 4.0 / 4036.0);
+
+        let streams = imux.into_inner().into_iter().map(CountingIO::into_inner);
+        let streams = streams.map(|s| CountingIO::new(futures::io::Cursor::new(s)));
+        let mut imux = IMuxAsync::with_config(streams.collect(), config);
+        assert_eq!(imux.count_skew(), 1.0);
+        block_on(imux.read()).unwrap();
+        let read = imux
+            .per_channel_counts()
+            .iter()
+            .map(|c| c.bytes_read)
+            .collect::<Vec<_>>();
+        assert_eq!(read, [1036, 1000, 1000, 1000]);
+
+        // A message small enough for a single channel
+        let single = ChannelCount {
+            bytes_read: 10,
+            bytes_written: 36,
+        };
+        let idle = ChannelCount::default();
+        assert_eq!(ChannelCount::skew(&[single, idle, idle, idle]), 4.0);
+    }
+
+    #[test]
+    fn chunk_size_is_bounded() {
+        let config = IMuxConfig::new();
+        assert_eq!(config.chunk_size(1 << 20, 4), 1 << 18);
+        assert_eq!(config.chunk_size(10_000, 4), 8192);
+        let config = IMuxConfig::new().min_chunk_size(100).max_chunk_size(1000);
+        assert_eq!(config.chunk_size(1 << 20, 4), 1000);
+        assert_eq!(config.chunk_size(1000, 4), 250);
+        assert_eq!(config.chunk_size(10, 4), 100);
+        let config = IMuxConfig::new()
+            .min_chunk_size(1000)
+            .small_message_channels(2);
+        assert_eq!(config.chunk_size(3000, 4), 1500);
+        assert_eq!(config.chunk_size(4000, 4), 1000);
+    }
+
+    /// Sends `messages` across `channels` channels with `config`, returning the
+    /// number of bytes written to each channel.
+    fn written(config: IMuxConfig, channels: usize, messages: &[&[u8]]) -> Vec<u64> {
+        use crate::counting::CountingIO;
+
+        let streams = (0..channels).map(|_| CountingIO::new(Vec::new())).collect();
+        let mut imux = IMuxSync::with_config(streams, config);
+        for message in messages {
+            imux.write(message).unwrap();
+        }
+        let streams = imux.into_inner();
+        let counts = streams.iter().map(CountingIO::bytes_written).collect();
+        let streams = streams.into_iter().map(|s| Cursor::new(s.into_inner()));
+        let mut imux = IMuxSync::with_config(streams.collect(), config);
+        for message in messages {
+            assert_eq!(imux.read().unwrap(), *message);
+        }
+        counts
+    }
+
+    #[test]
+    fn small_messages_use_fewer_channels() {
+        let config = IMuxConfig::new()
+            .min_chunk_size(1000)
+            .small_message_channels(2);
+        assert_eq!(written(config, 4, &[&[1; 3000]]), [1536, 1500, 0, 0]);
+        assert_eq!(written(config, 4, &[&[1; 8000]]), [2036, 2000, 2000, 2000]);
+    }
+
+    #[test]
+    fn large_messages_are_sent_in_rounds_of_chunks() {
+        let config = IMuxConfig::new().min_chunk_size(100).max_chunk_size(1000);
+        assert_eq!(written(config, 2, &[&[1; 5000]]), [3036, 2000]);
+    }
+
+    fn configs() -> Vec<IMuxConfig> {
+        let small = IMuxConfig::new().min_chunk_size(1).max_chunk_size(100);
+        vec![
+            IMuxConfig::new(),
+            IMuxConfig::new().checksums(true),
+            small,
+            small.checksums(true),
+            small.small_message_channels(1),
+            adaptive(100),
+            adaptive(100).checksums(true),
+        ]
+    }
+
+    fn messages() -> Vec<Vec<u8>> {
+        [0, 1, 99, 1000, 100_000]
+            .iter()
+            .map(|&len| (0..len).map(|i| (i * 7) as u8).collect())
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let messages = messages();
+        let messages = messages.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        for config in configs() {
+            for channels in [1, 3] {
+                let streams = send(config, channels, &messages);
+                let streams = streams.into_iter().map(Cursor::new).collect();
+                let mut imux = IMuxSync::with_config(streams, config);
+                for (i, message) in messages.iter().enumerate() {
+                    match i % 3 {
+                        0 => assert_eq!(imux.read().unwrap(), *message),
+                        1 => {
+                            let mut buf = vec![0u8; message.len()];
+                            imux.read_into(&mut buf).unwrap();
+                            assert_eq!(buf, *message);
+                        }
+                        _ => {
+                            let mut buf = vec![1u8; 10];
+                            imux.read_into_vec(&mut buf).unwrap();
+                            assert_eq!(buf, *message);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_async() {
+        let messages = messages();
+        for config in configs() {
+            let mut imux = IMuxAsync::with_config(vec![Vec::new(); 3], config);
+            for message in &messages {
+                block_on(imux.write(message)).unwrap();
+            }
+            let streams = imux
+                .into_inner()
+                .into_iter()
+                .map(futures::io::Cursor::new)
+                .collect();
+            let mut imux = IMuxAsync::with_config(streams, config);
+            for message in &messages {
+                assert_eq!(block_on(imux.read()).unwrap(), *message);
+            }
+        }
+    }
+
+    #[test]
+    fn header_magic_mismatch() {
+        let mut streams = send(IMuxConfig::new(), 1, &[&[1; 10]]);
+        streams[0][..4].copy_from_slice(b"XMUX");
+        rejects(IMuxConfig::new(), streams, "Invalid message header");
+    }
+
+    #[test]
+    fn header_version_mismatch() {
+        let mut streams = send(IMuxConfig::new(), 1, &[&[1; 10]]);
+        streams[0][4] = 2;
+        rejects(IMuxConfig::new(), streams, "version 2");
+    }
+
+    #[test]
+    fn header_channel_count_mismatch() {
+        let mut streams = send(IMuxConfig::new(), 2, &[&[1; 10]]);
+        streams.push(Vec::new());
+        rejects(IMuxConfig::new(), streams, "across 2 channels");
+    }
+
+    #[test]
+    fn header_chunk_size_mismatch() {
+        let streams = send(IMuxConfig::new().max_chunk_size(4), 1, &[&[1; 10]]);
+        rejects(IMuxConfig::new(), streams, "chunks of 4 bytes");
+        let streams = send(adaptive(4), 1, &[&[1; 10]]);
+        rejects(adaptive(8), streams, "chunks of 4 bytes");
+    }
+
+    #[test]
+    fn header_message_id_mismatch() {
+        let mut streams = send(IMuxConfig::new(), 1, &[&[1; 10]]);
+        streams[0][20..28].copy_from_slice(&1u64.to_le_bytes());
+        rejects(IMuxConfig::new(), streams, "Expected message 0");
+    }
+
+    #[test]
+    fn header_checksum_flag_mismatch() {
+        let config = IMuxConfig::new();
+        let streams = send(config, 1, &[&[1; 10]]);
+        rejects(config.checksums(true), streams, "Checksums are disabled");
+        let streams = send(config.checksums(true), 1, &[&[1; 10]]);
+        rejects(config, streams, "Checksums are enabled");
+    }
+
+    #[test]
+    fn header_schedule_mismatch() {
+        let streams = send(IMuxConfig::new(), 1, &[&[1; 10]]);
+        rejects(adaptive(4), streams, "the static schedule");
+        let streams = send(adaptive(4), 1, &[&[1; 10]]);
+        rejects(IMuxConfig::new(), streams, "the adaptive schedule");
+    }
+
+    #[test]
+    fn header_message_size_exceeded() {
+        let streams = send(IMuxConfig::new(), 1, &[&[1; 10]]);
+        rejects(IMuxConfig::new().max_message_size(9), streams, "exceeds");
+    }
+
+    #[test]
+    fn checksum_corruption() {
+        let config = IMuxConfig::new().max_chunk_size(4).checksums(true);
+        let mut streams = send(config, 1, &[&[1; 10]]);
+        // The second chunk follows the header and the first chunk and checksum
+        streams[0][36 + 8 + 1] ^= 1;
+        rejects(
+            config,
+            streams,
+            "Checksum mismatch in the chunk at offset 4",
+        );
+    }
+
+    #[test]
+    fn adaptive_checksum_corruption() {
+        let config = adaptive(4).checksums(true);
+        let mut streams = send(config, 1, &[&[1; 10]]);
+        // The second chunk follows the header and the first chunk with its
+        // header and checksum
+        streams[0][36 + 20 + 12 + 1] ^= 1;
+        rejects(
+            config,
+            streams,
+            "Checksum mismatch in the chunk at offset 4",
+        );
+    }
+
+    #[test]
+    fn adaptive_misaligned_chunk() {
+        let config = adaptive(4);
+        let mut stream = header(config, 1, 8);
+        stream.extend(adaptive_chunk(1, &[1; 4]));
+        rejects(config, vec![stream], "offset 1");
+    }
+
+    #[test]
+    fn adaptive_chunk_past_end() {
+        let config = adaptive(4);
+        let mut stream = header(config, 1, 8);
+        stream.extend(adaptive_chunk(8, &[1; 4]));
+        rejects(config, vec![stream], "offset 8");
+    }
+
+    #[test]
+    fn adaptive_short_chunk() {
+        let config = adaptive(4);
+        let mut stream = header(config, 1, 8);
+        stream.extend(adaptive_chunk(0, &[1; 3]));
+        rejects(config, vec![stream], "length 3");
+    }
+
+    #[test]
+    fn adaptive_missing_chunk() {
+        let config = adaptive(4);
+        let mut stream = header(config, 1, 8);
+        stream.extend(adaptive_chunk(4, &[1; 4]));
+        stream.extend(adaptive_chunk(0, &[]));
+        rejects(config, vec![stream], "Received 4 of 8");
+    }
+
+    /// Encodes a frame of a stream.
+    fn frame(seq: u64, len: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = seq.to_le_bytes().to_vec();
+        frame.extend_from_slice(&len.to_le_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// Reads `streams` to the end with an [`IMuxStream`], expecting it to fail
+    /// with an error of `kind` containing `expected`.
+    ///
+    /// [`IMuxStream`]: crate::imux::IMuxStream
+    fn stream_rejects(streams: Vec<Vec<u8>>, expected: &str, kind: ErrorKind) {
+        let channels = streams.into_iter().map(futures::io::Cursor::new).collect();
+        let mut stream = IMuxAsync::new(channels).into_stream();
+        let err = block_on(stream.read_to_end(&mut Vec::new())).unwrap_err();
+        assert_eq!(err.kind(), kind, "{}", err);
+        assert!(err.to_string().contains(expected), "{}", err);
+    }
+
+    #[test]
+    fn stream_round_trip() {
+        let message = (0..200_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let mut stream = IMuxAsync::new(vec![Vec::new(); 3]).into_stream();
+        block_on(stream.write_all(&message)).unwrap();
+        block_on(stream.flush()).unwrap();
+        let channels = stream
+            .into_inner()
+            .into_inner()
+            .into_iter()
+            .map(futures::io::Cursor::new)
+            .collect();
+        let mut received = Vec::new();
+        let mut stream = IMuxAsync::new(channels).into_stream();
+        block_on(stream.read_to_end(&mut received)).unwrap();
+        assert_eq!(received, message);
+    }
+
+    #[test]
+    fn stream_empty_frame() {
+        let streams = vec![frame(0, 0, &[])];
+        stream_rejects(streams, "Invalid frame length 0", ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn stream_oversized_frame() {
+        let streams = vec![frame(0, 65537, &[1; 16])];
+        stream_rejects(
+            streams,
+            "Invalid frame length 65537",
+            ErrorKind::InvalidData,
+        );
+    }
+
+    #[test]
+    fn stream_frame_out_of_order() {
+        let streams = vec![frame(1, 4, &[1; 4]), frame(0, 4, &[1; 4])];
+        stream_rejects(streams, "Expected frame 0", ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn stream_truncated_frame() {
+        let streams = vec![frame(0, 8, &[1; 4])];
+        stream_rejects(streams, "", ErrorKind::UnexpectedEof);
+    }
+
+    /// Reads `streams` to the end with an [`IMuxReader`], expecting it to fail
+    /// with an error of `kind` containing `expected`.
+    ///
+    /// [`IMuxReader`]: crate::imux::IMuxReader
+    fn reader_rejects(streams: Vec<Vec<u8>>, expected: &str, kind: ErrorKind) {
+        let channels = streams.into_iter().map(Cursor::new).collect();
+        let mut reader = IMuxSync::new(channels).into_reader();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), kind, "{}", err);
+        assert!(err.to_string().contains(expected), "{}", err);
+    }
+
+    #[test]
+    fn reader_round_trip() {
+        let message = (0..200_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let mut writer = IMuxSync::new(vec![Vec::new(); 3]).into_writer();
+        writer.write_all(&message).unwrap();
+        let channels = writer.into_inner().unwrap().into_inner();
+        let channels = channels.into_iter().map(Cursor::new).collect();
+        let mut received = Vec::new();
+        let mut reader = IMuxSync::new(channels).into_reader();
+        reader.read_to_end(&mut received).unwrap();
+        assert_eq!(received, message);
+    }
+
+    #[test]
+    fn writer_keeps_message_ids() {
+        let mut imux = IMuxSync::new(vec![Vec::new(); 2]);
+        imux.write(&[1; 100]).unwrap();
+        let mut imux = imux.into_writer().into_inner().unwrap();
+        imux.write(&[2; 100]).unwrap();
+        let channels = imux.into_inner().into_iter().map(Cursor::new).collect();
+        let mut imux = IMuxSync::new(channels);
+        assert_eq!(imux.read().unwrap(), [1; 100]);
+        assert_eq!(imux.read().unwrap(), [2; 100]);
+    }
+
+    /// A channel which panics when written to.
+    struct Panicking;
+
+    impl Write for Panicking {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            panic!("write to a panicking channel")
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writer_with_panicked_thread() {
+        let mut writer = IMuxSync::new(vec![Panicking]).into_writer();
+        writer.write_all(&[1; 10]).unwrap();
+        assert!(writer.into_inner().is_err());
+        let mut writer = IMuxSync::new(vec![Panicking]).into_writer();
+        writer.write_all(&[1; 10]).unwrap();
+        drop(writer);
+    }
+
+    #[test]
+    fn reader_empty_frame() {
+        let streams = vec![frame(0, 0, &[])];
+        reader_rejects(streams, "Invalid frame length 0", ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reader_oversized_frame() {
+        let streams = vec![frame(0, 65537, &[1; 16])];
+        reader_rejects(
+            streams,
+            "Invalid frame length 65537",
+            ErrorKind::InvalidData,
+        );
+    }
+
+    #[test]
+    fn reader_frame_out_of_order() {
+        let streams = vec![frame(1, 4, &[1; 4]), frame(0, 4, &[1; 4])];
+        reader_rejects(streams, "Expected frame 0", ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reader_truncated_frame() {
+        let streams = vec![frame(0, 8, &[1; 4])];
+        reader_rejects(streams, "", ErrorKind::UnexpectedEof);
+    }
+
+    /// Connects to `addr` with a garbage handshake, without a handshake and
+    /// with the handshake of a single channel, returning the latter.
+    fn connect_invalid(addr: SocketAddr) -> TcpStream {
+        TcpStream::connect(addr)
+            .unwrap()
+            .write_all(&[1; 24])
+            .unwrap();
+        drop(TcpStream::connect(addr).unwrap());
+        let mut hello = b"IMXH\x01\0\0\0".to_vec();
+        hello.extend_from_slice(&1u32.to_le_bytes());
+        hello.extend_from_slice(&0u32.to_le_bytes());
+        hello.extend_from_slice(&7u64.to_le_bytes());
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(&hello).unwrap();
+        stream
+    }
+
+    #[test]
+    fn accept_skips_invalid_handshakes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || IMuxSync::accept(&listener, 2));
+        let mut other = connect_invalid(addr);
+        let mut client = IMuxSync::connect(addr, 2).unwrap();
+        client.write(&[1; 1000]).unwrap();
+        client.flush().unwrap();
+        assert_eq!(server.join().unwrap().unwrap().read().unwrap(), [1; 1000]);
+        assert_eq!(other.read(&mut [0; 24]).unwrap(), 0);
+    }
+
+    #[cfg(feature = "async-std")]
+    #[test]
+    fn accept_skips_invalid_handshakes_async() {
+        block_on(async {
+            let listener = async_std::net::TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap();
+            let addr = listener.local_addr().unwrap();
+            let mut other = connect_invalid(addr);
+            let client = thread::spawn(move || IMuxSync::connect(addr, 2));
+            let mut server = IMuxAsync::accept(&listener, 2).await.unwrap();
+            let mut client = client.join().unwrap().unwrap();
+            client.write(&[1; 1000]).unwrap();
+            client.flush().unwrap();
+            assert_eq!(server.read().await.unwrap(), [1; 1000]);
+            assert_eq!(other.read(&mut [0; 24]).unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn accept_reads_handshakes_concurrently() {
+        let start = Instant::now();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _silent = TcpStream::connect(addr).unwrap();
+        let server = thread::spawn(move || IMuxSync::accept(&listener, 2));
+        let mut client = IMuxSync::connect(addr, 2).unwrap();
+        client.write(&[1; 1000]).unwrap();
+        client.flush().unwrap();
+        assert_eq!(server.join().unwrap().unwrap().read().unwrap(), [1; 1000]);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(feature = "async-std")]
+    #[test]
+    fn accept_reads_handshakes_concurrently_async() {
+        let start = Instant::now();
+        block_on(async {
+            let listener = async_std::net::TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap();
+            let addr = listener.local_addr().unwrap();
+            let _silent = TcpStream::connect(addr).unwrap();
+            let client = thread::spawn(move || IMuxSync::connect(addr, 2));
+            let mut server = IMuxAsync::accept(&listener, 2).await.unwrap();
+            let mut client = client.join().unwrap().unwrap();
+            client.write(&[1; 1000]).unwrap();
+            client.flush().unwrap();
+            assert_eq!(server.read().await.unwrap(), [1; 1000]);
+        });
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(feature = "async-std")]
+    #[test]
+    fn listener_accepts_concurrent_sessions() {
+        use crate::imux::IMuxListener;
+        use futures::StreamExt;
+
+        block_on(async {
+            let mut listener = IMuxListener::bind("127.0.0.1:0", 2).await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let clients = (0..2u8)
+                .map(|i| {
+                    thread::spawn(move || {
+                        let mut client = IMuxSync::connect(addr, 2).unwrap();
+                        client.write(&[i; 1000]).unwrap();
+                        client.flush().unwrap();
+                        client
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut received = Vec::new();
+            for _ in 0..2 {
+                let mut server = listener.next().await.unwrap().unwrap();
+                received.push(server.read().await.unwrap());
+            }
+            received.sort();
+            assert_eq!(received, [[0; 1000], [1; 1000]]);
+            for client in clients {
+                client.join().unwrap();
+            }
+        });
+    }
+
+    #[cfg(feature = "async-std")]
+    #[test]
+    fn listener_times_out_incomplete_sessions() {
+        use crate::imux::IMuxListener;
+        use futures::StreamExt;
+
+        block_on(async {
+            let listener = IMuxListener::bind("127.0.0.1:0", 2).await.unwrap();
+            let mut listener = listener.with_timeout(Duration::from_millis(100));
+            let mut hello = b"IMXH\x01\0\0\0".to_vec();
+            hello.extend_from_slice(&2u32.to_le_bytes());
+            hello.extend_from_slice(&0u32.to_le_bytes());
+            hello.extend_from_slice(&7u64.to_le_bytes());
+            let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            stream.write_all(&hello).unwrap();
+
+            let err = listener.next().await.unwrap().err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::TimedOut, "{}", err);
+            assert_eq!(
+                err.to_string(),
+                "Session 0000000000000007 timed out with 1 of 2 channels connected"
+            );
+            assert_eq!(stream.read(&mut [0; 24]).unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn connect_times_out_without_acknowledgement() {
+        // The connections are queued by the listener but never accepted
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let start = Instant::now();
+        let err = IMuxSync::connect(listener.local_addr().unwrap(), 2)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::TimedOut, "{}", err);
+        assert!(start.elapsed() >= Duration::from_secs(10));
+    }
+
+    #[cfg(feature = "async-std")]
+    #[test]
+    fn connect_times_out_without_acknowledgement_async() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let start = Instant::now();
+        let connect = IMuxAsync::connect(listener.local_addr().unwrap(), 2);
+        let err = block_on(connect).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::TimedOut, "{}", err);
+        assert!(start.elapsed() >= Duration::from_secs(10));
+    }
+}
+
+mod faulty {
+    use crate::faulty::{Fault, FaultSchedule, FaultyIO};
+    use crate::imux::{IMuxAsync, IMuxSync};
+    use futures::executor::block_on;
+    use std::io::{self, Cursor, ErrorKind};
+
+    /// Sends `messages` across `channels` channels, returning the bytes sent on
+    /// each channel.
+    fn send(channels: usize, messages: &[&[u8]]) -> Vec<Vec<u8>> {
+        let mut imux = IMuxSync::new(vec![Vec::new(); channels]);
+        for message in messages {
+            imux.write(message).unwrap();
+        }
+        imux.into_inner()
+    }
+
+    /// Wraps the channels of `streams` in `FaultyIO` objects following the
+    /// schedule `schedule` returns for each channel.
+    fn faulty<C>(
+        streams: Vec<Vec<u8>>,
+        cursor: impl Fn(Vec<u8>) -> C,
+        schedule: impl Fn(usize) -> FaultSchedule,
+    ) -> Vec<FaultyIO<C>> {
+        streams
+            .into_iter()
+            .enumerate()
+            .map(|(i, stream)| FaultyIO::new(cursor(stream), schedule(i)))
+            .collect()
+    }
+
+    fn read_sync(
+        streams: Vec<Vec<u8>>,
+        schedule: impl Fn(usize) -> FaultSchedule,
+    ) -> io::Result<Vec<u8>> {
+        IMuxSync::new(faulty(streams, Cursor::new, schedule)).read()
+    }
+
+    fn read_async(
+        streams: Vec<Vec<u8>>,
+        schedule: impl Fn(usize) -> FaultSchedule,
+    ) -> io::Result<Vec<u8>> {
+        let channels = faulty(streams, futures::io::Cursor::new, schedule);
+        block_on(IMuxAsync::new(channels).read())
+    }
+
+    #[test]
+    fn read_retries_short_reads() {
+        let message = vec![5u8; 1 << 20];
+        let streams = send(4, &[&message]);
+        let faults = [Fault::Short, Fault::Interrupted];
+        let schedule = |i| FaultSchedule::random(i as u64, 0.5, &faults);
+        assert_eq!(read_sync(streams.clone(), schedule).unwrap(), message);
+
+        let faults = [Fault::Short, Fault::WouldBlock];
+        let schedule = |i| FaultSchedule::random(i as u64, 0.5, &faults);
+        let mut imux = IMuxAsync::new(faulty(streams, futures::io::Cursor::new, schedule));
+        assert_eq!(block_on(imux.read()).unwrap(), message);
+        assert!(imux.get_ref().iter().all(|c| c.faults_injected() > 0));
+    }
+
+    #[test]
+    fn read_fails_on_eof() {
+        let streams = send(4, &[&vec![5u8; 1 << 20]]);
+        let schedule = |i| match i {
+            3 => FaultSchedule::new().on_read(0, Fault::Eof),
+            _ => FaultSchedule::new(),
+        };
+        let err = read_sync(streams.clone(), schedule).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "{}", err);
+        let err = read_async(streams, schedule).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "{}", err);
+    }
+
+    #[test]
+    fn read_fails_on_errors() {
+        let streams = send(4, &[&vec![5u8; 1 << 20]]);
+        let schedule = |i| match i {
+            0 => FaultSchedule::new().on_read(1, Fault::Error(ErrorKind::ConnectionReset)),
+            _ => FaultSchedule::new(),
+        };
+        let err = read_sync(streams.clone(), schedule).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionReset, "{}", err);
+        let err = read_async(streams, schedule).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionReset, "{}", err);
+    }
+
+    #[cfg(feature = "async-std")]
+    mod threaded {
+        use super::*;
+        use crate::threaded::ThreadedReader;
+
+        /// Returns a `ThreadedReader` receiving the bytes of `streams`.
+        fn reader(
+            streams: Vec<Vec<u8>>,
+            schedule: impl Fn(usize) -> FaultSchedule,
+        ) -> ThreadedReader {
+            ThreadedReader::new(IMuxAsync::new(faulty(
+                streams,
+                futures::io::Cursor::new,
+                schedule,
+            )))
+        }
+
+        #[test]
+        fn read_retries_short_reads() {
+            let message = vec![5u8; 1 << 20];
+            let streams = send(4, &[&[0], &message, &[0], b"done"]);
+            let faults = [Fault::Short, Fault::WouldBlock];
+            let mut reader = reader(streams, |i| FaultSchedule::random(i as u64, 0.5, &faults));
+            assert_eq!(block_on(reader.read()).unwrap(), message);
+            assert_eq!(block_on(reader.read()).unwrap(), b"done");
+        }
+
+        #[test]
+        fn read_fails_on_eof() {
+            let streams = send(2, &[&[0], b"message"]);
+            let mut reader = reader(streams, |_| FaultSchedule::new());
+            assert_eq!(block_on(reader.read()).unwrap(), b"message");
+            let err = block_on(reader.read()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "{}", err);
+        }
+
+        #[test]
+        fn read_fails_on_errors() {
+            let streams = send(2, &[&[0], b"message"]);
+            let error = Fault::Error(ErrorKind::ConnectionReset);
+            let mut reader = reader(streams, |i| match i {
+                0 => FaultSchedule::new().on_read(0, error),
+                _ => FaultSchedule::new(),
+            });
+            let err = block_on(reader.read()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset, "{}", err);
+            // Clones made after the reader failed receive its error as well
+            let err = block_on(reader.clone().read()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset, "{}", err);
+        }
+
+        #[test]
+        fn read_fails_on_unknown_reader() {
+            let streams = send(2, &[&[1], b"message"]);
+            let mut reader = reader(streams, |_| FaultSchedule::new());
+            let err = block_on(reader.read()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", err);
+        }
+    }
+}
+
+#[cfg(any(feature = "serde", feature = "ark-serialize"))]
+mod typed {
+    use crate::imux::{IMuxAsync, IMuxConfig, IMuxSync};
+    use futures::executor::block_on;
+    use std::io::{self, Cursor, ErrorKind};
+
+    /// Sends the messages written by `send` across 3 channels, returning the
+    /// bytes sent on each channel.
+    fn send(send: impl FnOnce(&mut IMuxSync<Vec<u8>>) -> io::Result<()>) -> Vec<Vec<u8>> {
+        let mut imux = IMuxSync::new(vec![Vec::new(); 3]);
+        send(&mut imux).unwrap();
+        imux.into_inner()
+    }
+
+    fn receiver(streams: &[Vec<u8>], config: IMuxConfig) -> IMuxSync<Cursor<Vec<u8>>> {
+        IMuxSync::with_config(streams.iter().cloned().map(Cursor::new).collect(), config)
+    }
+
+    fn receiver_async(
+        streams: &[Vec<u8>],
+        config: IMuxConfig,
+    ) -> IMuxAsync<futures::io::Cursor<Vec<u8>>> {
+        let channels = streams.iter().cloned().map(futures::io::Cursor::new);
+        IMuxAsync::with_config(channels.collect(), config)
+    }
+
+    /// Checks that `err` reports the 8 byte message of which only 4 bytes
+    /// were deserialized.
+    fn assert_trailing_bytes(err: io::Error) {
+        assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", err);
+        assert_eq!(
+            err.to_string(),
+            "Only 4 of the 8 message bytes were deserialized"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    mod serde {
+        use super::*;
+
+        fn values() -> Vec<(u64, String)> {
+            (0..1000).map(|i| (i, format!("value {}", i))).collect()
+        }
+
+        #[test]
+        fn round_trip() {
+            let streams = send(|imux| {
+                imux.send(&values())?;
+                imux.send("done")
+            });
+            let mut imux = receiver(&streams, IMuxConfig::new());
+            assert_eq!(imux.recv::<Vec<(u64, String)>>().unwrap(), values());
+            assert_eq!(imux.recv::<String>().unwrap(), "done");
+
+            let mut imux = receiver_async(&streams, IMuxConfig::new());
+            assert_eq!(
+                block_on(imux.recv::<Vec<(u64, String)>>()).unwrap(),
+                values()
+            );
+            assert_eq!(block_on(imux.recv::<String>()).unwrap(), "done");
+
+            let mut imux = IMuxAsync::new(vec![futures::io::Cursor::new(Vec::new()); 3]);
+            block_on(imux.send(&values())).unwrap();
+            block_on(imux.send("done")).unwrap();
+            let streams = imux.into_inner().into_iter().map(|c| c.into_inner());
+            let mut imux = receiver(&streams.collect::<Vec<_>>(), IMuxConfig::new());
+            assert_eq!(imux.recv::<Vec<(u64, String)>>().unwrap(), values());
+            assert_eq!(imux.recv::<String>().unwrap(), "done");
+        }
+
+        #[test]
+        fn trailing_bytes_are_rejected() {
+            let streams = send(|imux| imux.send(&7u64));
+            assert_trailing_bytes(
+                receiver(&streams, IMuxConfig::new())
+                    .recv::<u32>()
+                    .unwrap_err(),
+            );
+            let mut imux = receiver_async(&streams, IMuxConfig::new());
+            assert_trailing_bytes(block_on(imux.recv::<u32>()).unwrap_err());
+        }
+
+        #[test]
+        fn max_message_size_is_enforced() {
+            let streams = send(|imux| imux.send(&values()));
+            let config = IMuxConfig::new().max_message_size(1000);
+            let err = receiver(&streams, config)
+                .recv::<Vec<(u64, String)>>()
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", err);
+            assert!(
+                err.to_string().contains("exceeds the maximum message size"),
+                "{}",
+                err
+            );
+            let mut imux = receiver_async(&streams, config);
+            let err = block_on(imux.recv::<Vec<(u64, String)>>()).unwrap_err();
+            assert!(
+                err.to_string().contains("exceeds the maximum message size"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[cfg(feature = "ark-serialize")]
+    mod canonical {
+        use super::*;
+        use crate::imux::Compression;
+
+        #[test]
+        fn round_trip() {
+            let values = (0..10_000).collect::<Vec<u64>>();
+            for compression in [Compression::Compressed, Compression::Uncompressed] {
+                let streams = send(|imux| {
+                    imux.send_canonical(&values, compression)?;
+                    imux.send_canonical(&5u32, compression)
+                });
+                let mut imux = receiver(&streams, IMuxConfig::new());
+                assert_eq!(
+                    imux.recv_canonical::<Vec<u64>>(compression).unwrap(),
+                    values
+                );
+                assert_eq!(imux.recv_canonical::<u32>(compression).unwrap(), 5);
+
+                let mut imux = receiver_async(&streams, IMuxConfig::new());
+                let received = block_on(imux.recv_canonical::<Vec<u64>>(compression));
+                assert_eq!(received.unwrap(), values);
+                assert_eq!(
+                    block_on(imux.recv_canonical::<u32>(compression)).unwrap(),
+                    5
+                );
+            }
+        }
+
+        #[test]
+        fn trailing_bytes_are_rejected() {
+            let streams = send(|imux| imux.send_canonical(&7u64, Compression::Compressed));
+            let mut imux = receiver(&streams, IMuxConfig::new());
+            assert_trailing_bytes(
+                imux.recv_canonical::<u32>(Compression::Compressed)
+                    .unwrap_err(),
+            );
+            let mut imux = receiver_async(&streams, IMuxConfig::new());
+            let received = block_on(imux.recv_canonical::<u32>(Compression::Compressed));
+            assert_trailing_bytes(received.unwrap_err());
+        }
+
+        #[test]
+        fn max_message_size_is_enforced() {
+            let values = vec![1u64; 1000];
+            let streams = send(|imux| imux.send_canonical(&values, Compression::Compressed));
+            let config = IMuxConfig::new().max_message_size(1000);
+            let mut imux = receiver(&streams, config);
+            let err = imux
+                .recv_canonical::<Vec<u64>>(Compression::Compressed)
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", err);
+            assert!(
+                err.to_string().contains("exceeds the maximum message size"),
+                "{}",
+                err
+            );
+        }
+    }
+}
+
+mod recording {
+    use crate::{
+        recording::{Direction, Record, RecordingIO, Transcript},
+        replay::ReplayIO,
+    };
+    use std::{
+        io::{self, Cursor, ErrorKind, Read, Write},
+        path::{Path, PathBuf},
+    };
+
+    /// A connection to a peer which sends `incoming` and receives `outgoing`.
+    struct Peer {
+        incoming: Cursor<Vec<u8>>,
+        outgoing: Vec<u8>,
+    }
+
+    impl Read for Peer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.incoming.read(buf)
+        }
+    }
+
+    impl Write for Peer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.outgoing.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Returns a path for the transcript of the test `name`.
+    fn transcript_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("io-utils-{}-{}.iotr", name, std::process::id()))
+    }
+
+    /// Runs a session which sends a request and receives a response.
+    fn session<I: Read + Write>(io: &mut I) -> io::Result<()> {
+        io.write_all(b"request")?;
+        let mut response = [0u8; 8];
+        io.read_exact(&mut response)?;
+        assert_eq!(&response, b"response");
+        io.write_all(b"done")
+    }
+
+    /// Records a session to the file at `path`.
+    fn record(path: &Path) {
+        let peer = Peer {
+            incoming: Cursor::new(b"response".to_vec()),
+            outgoing: Vec::new(),
+        };
+        let mut io = RecordingIO::create(peer, path).unwrap();
+        session(&mut io).unwrap();
+        let (peer, _) = io.finish().unwrap();
+        assert_eq!(peer.outgoing, b"requestdone");
+    }
+
+    #[test]
+    fn transcript_round_trip() {
+        let path = transcript_path("round-trip");
+        record(&path);
+        let transcript = Transcript::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let calls = transcript
+            .records
+            .iter()
+            .map(
+                |Record {
+                     direction, data, ..
+                 }| (*direction, data.as_slice()),
+            )
+            .collect::<Vec<_>>();
+        assert_eq!(
+            calls,
+            [
+                (Direction::Write, &b"request"[..]),
+                (Direction::Read, &b"response"[..]),
+                (Direction::Write, &b"done"[..]),
+            ]
+        );
+        let timestamps = transcript.records.iter().map(|r| r.timestamp);
+        assert!(timestamps
+            .clone()
+            .zip(timestamps.skip(1))
+            .all(|(a, b)| a <= b));
+        assert_eq!(transcript.bytes(Direction::Write), b"requestdone");
+        assert_eq!(transcript.bytes(Direction::Read), b"response");
+    }
+
+    #[test]
+    fn replay_round_trip() {
+        let path = transcript_path("replay");
+        record(&path);
+        let mut replay = ReplayIO::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        session(&mut replay).unwrap();
+        assert!(replay.is_complete());
+        assert_eq!(replay.divergence(), None);
+        assert_eq!(replay.read(&mut [0; 8]).unwrap(), 0);
+    }
+
+    #[test]
+    fn replay_reports_first_divergence() {
+        let path = transcript_path("divergence");
+        record(&path);
+        let transcript = Transcript::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut replay = ReplayIO::new(&transcript);
+        replay.write_all(b"req").unwrap();
+        let err = replay.write_all(b"uesX").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", err);
+        assert_eq!(
+            err.to_string(),
+            "Write diverged from the transcript at offset 6"
+        );
+        assert_eq!(replay.divergence(), Some(6));
+        // Writes keep failing at the first divergence
+        let err = replay.write_all(b"t").unwrap_err();
+        assert!(err.to_string().ends_with("offset 6"), "{}", err);
+        assert!(!replay.is_complete());
+
+        // Writing past the end of the recording diverges as well
+        let mut replay = ReplayIO::new(&transcript);
+        replay.write_all(b"request").unwrap();
+        replay.read_exact(&mut [0; 8]).unwrap();
+        assert!(replay.write_all(b"done!").is_err());
+        assert_eq!(replay.divergence(), Some(11));
+    }
+}
+
+#[cfg(feature = "metrics")]
+mod metrics {
+    use crate::{
+        counting::CountingIO,
+        metrics::{ChannelMetrics, Metrics},
+    };
+
+    fn metrics() -> Metrics {
+        Metrics {
+            channels: vec![
+                ChannelMetrics {
+                    bytes_read: 10,
+                    bytes_written: 20,
+                    rounds: 2,
+                    read_calls: Some(1),
+                    write_calls: Some(2),
+                    read_blocked_seconds: Some(0.5),
+                    write_blocked_seconds: None,
+                },
+                ChannelMetrics {
+                    bytes_read: 5,
+                    rounds: 3,
+                    read_calls: Some(4),
+                    ..ChannelMetrics::default()
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            metrics().to_json(),
+            concat!(
+                r#"{"channels":["#,
+                r#"{"bytes_read":10,"bytes_written":20,"rounds":2,"read_calls":1,"#,
+                r#""write_calls":2,"read_blocked_seconds":0.5,"write_blocked_seconds":null},"#,
+                r#"{"bytes_read":5,"bytes_written":0,"rounds":3,"read_calls":4,"#,
+                r#""write_calls":null,"read_blocked_seconds":null,"write_blocked_seconds":null}],"#,
+                r#""aggregate":{"bytes_read":15,"bytes_written":20,"rounds":3,"read_calls":5,"#,
+                r#""write_calls":2,"read_blocked_seconds":0.5,"write_blocked_seconds":null}}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn prometheus() {
+        assert_eq!(
+            metrics().to_prometheus("imux"),
+            concat!(
+                "# HELP imux_bytes_read_total Number of bytes read.\n",
+                "# TYPE imux_bytes_read_total counter\n",
+                "imux_bytes_read_total{channel=\"0\"} 10\n",
+                "imux_bytes_read_total{channel=\"1\"} 5\n",
+                "imux_bytes_read_total{channel=\"all\"} 15\n",
+                "# HELP imux_bytes_written_total Number of bytes written.\n",
+                "# TYPE imux_bytes_written_total counter\n",
+                "imux_bytes_written_total{channel=\"0\"} 20\n",
+                "imux_bytes_written_total{channel=\"1\"} 0\n",
+                "imux_bytes_written_total{channel=\"all\"} 20\n",
+                "# HELP imux_rounds_total Number of communication rounds.\n",
+                "# TYPE imux_rounds_total counter\n",
+                "imux_rounds_total{channel=\"0\"} 2\n",
+                "imux_rounds_total{channel=\"1\"} 3\n",
+                "imux_rounds_total{channel=\"all\"} 3\n",
+                "# HELP imux_read_calls_total Number of read calls.\n",
+                "# TYPE imux_read_calls_total counter\n",
+                "imux_read_calls_total{channel=\"0\"} 1\n",
+                "imux_read_calls_total{channel=\"1\"} 4\n",
+                "imux_read_calls_total{channel=\"all\"} 5\n",
+                "# HELP imux_write_calls_total Number of write calls.\n",
+                "# TYPE imux_write_calls_total counter\n",
+                "imux_write_calls_total{channel=\"0\"} 2\n",
+                "imux_write_calls_total{channel=\"all\"} 2\n",
+                "# HELP imux_read_blocked_seconds_total Time spent blocked in reads.\n",
+                "# TYPE imux_read_blocked_seconds_total counter\n",
+                "imux_read_blocked_seconds_total{channel=\"0\"} 0.5\n",
+                "imux_read_blocked_seconds_total{channel=\"all\"} 0.5\n",
+            )
+        );
+    }
+
+    #[test]
+    fn snapshot_of_counting_io() {
+        let mut io = CountingIO::new(Vec::new());
+        std::io::Write::write_all(&mut io, &[0; 7]).unwrap();
+        assert_eq!(
+            Metrics::from(&io).to_json(),
+            concat!(
+                r#"{"channels":[{"bytes_read":0,"bytes_written":7,"rounds":1,"read_calls":null,"#,
+                r#""write_calls":null,"read_blocked_seconds":null,"write_blocked_seconds":null}],"#,
+                r#""aggregate":{"bytes_read":0,"bytes_written":7,"rounds":1,"read_calls":null,"#,
+                r#""write_calls":null,"read_blocked_seconds":null,"write_blocked_seconds":null}}"#,
+            )
+        );
+    }
+}
+
+#[cfg(feature = "tokio")]
+mod tokio {
+    use crate::{
+        compat::TokioCompat,
+        counting::CountingIO,
+        imux::{IMuxAsync, IMuxSync},
+        threaded::ThreadedReader,
+    };
+    use futures::{executor::block_on, future::poll_fn};
+    use std::{io::ErrorKind, pin::Pin};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    #[test]
+    fn counting_io_implements_tokio_traits() {
+        let mut io = CountingIO::new(&b"hello"[..]);
+        let mut buf = [0u8; 8];
+        let mut buf = ReadBuf::new(&mut buf);
+        block_on(poll_fn(|ctx| Pin::new(&mut io).poll_read(ctx, &mut buf))).unwrap();
+        assert_eq!((buf.filled(), io.bytes_read()), (&b"hello"[..], 5));
+
+        let mut io = CountingIO::new(Vec::new());
+        let written = block_on(poll_fn(|ctx| Pin::new(&mut io).poll_write(ctx, b"abc")));
+        assert_eq!((written.unwrap(), io.bytes_written()), (3, 3));
+        assert_eq!(io.into_inner(), b"abc");
+    }
+
+    #[test]
+    fn imux_over_tokio_streams() {
+        let mut imux = IMuxAsync::new_tokio(vec![Vec::new(); 3]);
+        block_on(imux.write(&[7; 100_000])).unwrap();
+        let streams = imux.into_inner().into_iter().map(TokioCompat::into_inner);
+        let streams = streams.collect::<Vec<_>>();
+        let mut imux = IMuxAsync::new_tokio(streams.iter().map(Vec::as_slice).collect());
+        assert_eq!(block_on(imux.read()).unwrap(), [7; 100_000]);
+    }
+
+    #[test]
+    fn threaded_reader_on_tokio() {
+        let mut imux = IMuxSync::new(vec![Vec::new(); 2]);
+        for message in [&[0][..], b"first", &[1], b"second"] {
+            imux.write(message).unwrap();
+        }
+        let streams = imux.into_inner().into_iter();
+        let imux = IMuxAsync::new(streams.map(futures::io::Cursor::new).collect());
+        // The reader task only runs once the test awaits, so both readers
+        // exist before any message is received
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut first = ThreadedReader::new_tokio(imux);
+            let mut second = first.clone();
+            assert_eq!(first.read().await.unwrap(), b"first");
+            assert_eq!(second.read().await.unwrap(), b"second");
+            let err = first.read().await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "{}", err);
+        });
+    }
+}
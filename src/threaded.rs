@@ -16,6 +16,12 @@
 //! sending large messages over slow networks. However, they do not currently
 //! expose interfaces for streams using the [`CountingIO`][counting] wrapper.
 //!
+//! A [`ThreadedReader`] receives messages on a background task, which is
+//! spawned on the `async-std` runtime by [`ThreadedReader::new`] or on the
+//! current tokio runtime by [`ThreadedReader::new_tokio`]. This module is only
+//! available with at least one of the `async-std` and `tokio` features
+//! enabled.
+//!
 //! **This module is still early in development and will most likely contain
 //! bugs**
 //!
@@ -23,22 +29,29 @@
 //! [counting]: `crate::counting::CountingIO`
 
 use crate::imux::IMuxAsync;
-use async_std::{
-    channel,
-    sync::{Arc, Mutex},
-    task,
+use futures::{
+    channel::mpsc,
+    executor,
+    future::{BoxFuture, RemoteHandle},
+    lock::Mutex,
+    AsyncRead, AsyncWrite, FutureExt, StreamExt,
+};
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
 };
-use futures::{AsyncRead, AsyncWrite};
-use std::sync::atomic::{AtomicU8, Ordering};
 
 /// A reader that can be safely cloned and sent between threads.
 ///
 /// This type assumes that the order of clones are in sync with the
 /// clones of the corresponding writer.
 pub struct ThreadedReader {
-    senders: Arc<Mutex<Vec<channel::Sender<Vec<u8>>>>>,
-    receiver: channel::Receiver<Vec<u8>>,
-    handle: Arc<async_std::task::JoinHandle<()>>,
+    shared: Arc<std::sync::Mutex<Shared>>,
+    receiver: mpsc::UnboundedReceiver<Vec<u8>>,
+    handle: Arc<RemoteHandle<()>>,
 }
 
 /// A writer that can be safely cloned and sent between threads.
@@ -51,56 +64,139 @@ pub struct ThreadedWriter<W: 'static + AsyncWrite + Unpin + Send> {
     writer: Arc<Mutex<IMuxAsync<W>>>,
 }
 
+/// The state shared between the clones of a [`ThreadedReader`] and its
+/// background reader task.
+#[derive(Default)]
+struct Shared {
+    senders: Vec<mpsc::UnboundedSender<Vec<u8>>>,
+    // The error which stopped the background reader task, once it stopped
+    error: Option<io::Error>,
+}
+
 // TODO: Better error handling
 // TODO: Check edge cases when certain channels die before others
 
 impl ThreadedReader {
     /// Constructs a new `ThreadedReader` object.
     ///
-    /// A new thread is spawned containing the wrapped network stream. This
-    /// thread will communicate with all cloned readers using channels.
+    /// A new `async-std` task is spawned containing the wrapped network
+    /// stream. This task will communicate with all cloned readers using
+    /// channels.
+    #[cfg(feature = "async-std")]
     pub fn new<R: 'static + AsyncRead + Unpin + Send>(reader: IMuxAsync<R>) -> Self {
+        Self::with_spawner(reader, |task| {
+            async_std::task::spawn(task);
+        })
+    }
+
+    /// Constructs a new `ThreadedReader` object.
+    ///
+    /// A new task is spawned on the current tokio runtime containing the
+    /// wrapped network stream. This task will communicate with all cloned
+    /// readers using channels.
+    ///
+    /// Dropping the last clone of the reader blocks until the background task
+    /// finishes, so the runtime must be multi-threaded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn new_tokio<R: 'static + AsyncRead + Unpin + Send>(reader: IMuxAsync<R>) -> Self {
+        Self::with_spawner(reader, |task| {
+            tokio::spawn(task);
+        })
+    }
+
+    /// Constructs a new `ThreadedReader` object whose background reader task
+    /// is spawned by `spawn`.
+    fn with_spawner<R, S>(reader: IMuxAsync<R>, spawn: S) -> Self
+    where
+        R: 'static + AsyncRead + Unpin + Send,
+        S: FnOnce(BoxFuture<'static, ()>),
+    {
         // Setup a new channel to be used
-        let (sender, receiver) = channel::unbounded();
-        let senders = Arc::new(Mutex::new(vec![sender]));
-        let senders_clone = senders.clone();
-        // Start the background reader thread
-        let handle = Arc::new(task::spawn(async {
-            Self::read_loop(reader, senders_clone).await
+        let (sender, receiver) = mpsc::unbounded();
+        let shared = Arc::new(std::sync::Mutex::new(Shared {
+            senders: vec![sender],
+            error: None,
         }));
+        // Start the background reader task
+        let (task, handle) = Self::read_loop(reader, shared.clone()).remote_handle();
+        spawn(task.boxed());
         Self {
-            senders,
+            shared,
             receiver,
-            handle,
+            handle: Arc::new(handle),
         }
     }
 
-    /// The loop executed by the background reader thread
+    /// The loop executed by the background reader task
     async fn read_loop<R: 'static + AsyncRead + Unpin + Send>(
         mut reader: IMuxAsync<R>,
-        senders: Arc<Mutex<Vec<channel::Sender<Vec<u8>>>>>,
+        shared: Arc<std::sync::Mutex<Shared>>,
     ) {
         // Receive messages from the reader in a loop while connection is open
-        while let Ok(thread_num) = reader.read().await {
-            // The thread number will always be followed by a message
-            let msg = reader.read().await.unwrap();
-            // Get the lock for the senders and attempt to send
-            let senders_lock = senders.lock().await;
-            senders_lock[thread_num[0] as usize]
-                .send(msg)
-                .await
-                .unwrap();
-        }
+        let err = loop {
+            if let Err(e) = Self::forward(&mut reader, &shared).await {
+                break e;
+            }
+        };
+        let mut shared = shared.lock().unwrap();
+        shared.error = Some(err);
+        // Closing the channels wakes up the readers waiting for a message
+        shared.senders.clear();
+    }
+
+    /// Receives a message from `reader` and sends it to the reader it was
+    /// written for.
+    async fn forward<R: AsyncRead + Unpin>(
+        reader: &mut IMuxAsync<R>,
+        shared: &std::sync::Mutex<Shared>,
+    ) -> Result<(), io::Error> {
+        let thread_num = reader.read().await?;
+        // The thread number will always be followed by a message
+        let msg = reader.read().await?;
+        // Get the lock for the senders and attempt to send
+        let shared = shared.lock().unwrap();
+        let sender = match thread_num[..] {
+            [num] => shared.senders.get(num as usize),
+            _ => None,
+        };
+        let sender = sender.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Received a message for unknown reader {:?}", thread_num),
+            )
+        })?;
+        // The reader may have been dropped, which discards its messages
+        let _ = sender.unbounded_send(msg);
+        Ok(())
     }
 
     /// Receive a message.
-    pub async fn read(&mut self) -> Vec<u8> {
-        self.receiver.recv().await.unwrap()
+    ///
+    /// Once the background reader task fails to receive a message, all
+    /// clones return its error instead.
+    pub async fn read(&mut self) -> Result<Vec<u8>, io::Error> {
+        match self.receiver.next().await {
+            Some(msg) => Ok(msg),
+            None => {
+                let shared = self.shared.lock().unwrap();
+                Err(shared.error.as_ref().map_or_else(
+                    || io::Error::from(io::ErrorKind::BrokenPipe),
+                    |err| io::Error::new(err.kind(), err.to_string()),
+                ))
+            }
+        }
     }
 }
 
 impl<W: 'static + AsyncWrite + Unpin + Send> ThreadedWriter<W> {
     /// Constructs a new `ThreadedWriter` object.
+    ///
+    /// Writes are performed by the calling task, so a `ThreadedWriter` works
+    /// with any runtime.
     pub fn new(writer: IMuxAsync<W>) -> Self {
         Self {
             num: 0,
@@ -122,13 +218,14 @@ impl<W: 'static + AsyncWrite + Unpin + Send> ThreadedWriter<W> {
 impl Clone for ThreadedReader {
     fn clone(&self) -> Self {
         // Create a new channel and add it to the `senders` vector
-        let (sender, receiver) = channel::unbounded();
-        task::block_on(async {
-            let mut senders = self.senders.lock().await;
-            senders.push(sender);
-        });
+        let (sender, receiver) = mpsc::unbounded();
+        let mut shared = self.shared.lock().unwrap();
+        // Once the background reader task stopped, the channel stays closed
+        if shared.error.is_none() {
+            shared.senders.push(sender);
+        }
         Self {
-            senders: self.senders.clone(),
+            shared: self.shared.clone(),
             receiver,
             handle: self.handle.clone(),
         }
@@ -147,12 +244,9 @@ impl<W: 'static + AsyncWrite + Unpin + Send> Clone for ThreadedWriter<W> {
 
 impl Drop for ThreadedReader {
     fn drop(&mut self) {
-        // If this is the last writer still up, wait for the thread to finish
-        match Arc::get_mut(&mut self.handle) {
-            Some(h) => {
-                task::block_on(async { h.await });
-            }
-            None => {}
+        // If this is the last reader still up, wait for the task to finish
+        if let Some(h) = Arc::get_mut(&mut self.handle) {
+            executor::block_on(h);
         }
     }
 }